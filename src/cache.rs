@@ -0,0 +1,154 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tracing::warn;
+
+use crate::cli;
+use crate::decompress::Hints;
+
+// Validators of the cached response, sent back as conditional request headers
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheMeta {
+    pub url: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    // Kept to recognise compressed bodies, entries written before they existed have none
+    #[serde(default)]
+    pub content_type: Option<String>,
+    #[serde(default)]
+    pub content_encoding: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl CacheMeta {
+    pub fn hints(&self) -> Hints {
+        Hints {
+            content_type: self.content_type.clone(),
+            content_encoding: self.content_encoding.clone(),
+        }
+    }
+}
+
+// The body stays on disk, it's decompressed as it's read
+pub struct CacheEntry {
+    pub meta: CacheMeta,
+    pub body: PathBuf,
+}
+
+pub fn dir() -> Option<&'static Path> {
+    cli::CLI.cache_dir.as_deref()
+}
+
+// File name safe digest of an arbitrary string
+pub fn key(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect::<String>()
+}
+
+fn paths(dir: &Path, url: &str) -> (PathBuf, PathBuf) {
+    let key = key(url);
+    (
+        dir.join(format!("{key}.json")),
+        dir.join(format!("{key}.body")),
+    )
+}
+
+pub async fn load(url: &str) -> Option<CacheEntry> {
+    let (meta_path, body_path) = paths(dir()?, url);
+    let meta = tokio::fs::read(&meta_path).await.ok()?;
+    let meta = match serde_json::from_slice::<CacheMeta>(&meta) {
+        Ok(meta) => meta,
+        Err(e) => {
+            warn!(path = %meta_path.display(), error = %e, "Ignoring unreadable cache entry");
+            return None;
+        }
+    };
+    if !tokio::fs::try_exists(&body_path).await.unwrap_or(false) {
+        return None;
+    }
+    Some(CacheEntry {
+        meta,
+        body: body_path,
+    })
+}
+
+// A body written to the cache as it downloads, `commit` puts it in place once it decoded and
+// dropping it removes what was written
+pub struct Pending {
+    dir: &'static Path,
+    meta: CacheMeta,
+    tmp_body: PathBuf,
+    file: tokio::fs::File,
+}
+
+pub async fn begin(meta: CacheMeta) -> Option<Pending> {
+    let dir = dir()?;
+    // Sources sharing a URL download side by side, each into a file of its own
+    let (_, body_path) = paths(dir, &meta.url);
+    let tmp_body = body_path.with_extension(format!("body.{:08x}.tmp", rand::random::<u32>()));
+    let file = match tokio::fs::create_dir_all(dir).await {
+        Ok(()) => tokio::fs::File::create(&tmp_body).await,
+        Err(e) => Err(e),
+    };
+    match file {
+        Ok(file) => Some(Pending {
+            dir,
+            meta,
+            tmp_body,
+            file,
+        }),
+        Err(e) => {
+            warn!(url = %meta.url, error = %e, "Error writing cache entry");
+            None
+        }
+    }
+}
+
+impl Pending {
+    pub async fn write(&mut self, chunk: &[u8]) -> std::io::Result<()> {
+        self.file.write_all(chunk).await
+    }
+
+    pub async fn commit(mut self) {
+        if let Err(e) = self.put_in_place().await {
+            warn!(url = %self.meta.url, error = %e, "Error writing cache entry");
+        }
+    }
+
+    // Body first and both through a rename, a crash never leaves metadata pointing at a partial body
+    async fn put_in_place(&mut self) -> std::io::Result<()> {
+        self.file.flush().await?;
+        let (_, body_path) = paths(self.dir, &self.meta.url);
+        tokio::fs::rename(&self.tmp_body, &body_path).await?;
+        write_meta(self.dir, &self.meta).await
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        // Gone already once it was put in place
+        let _ = std::fs::remove_file(&self.tmp_body);
+    }
+}
+
+async fn write_meta(dir: &Path, meta: &CacheMeta) -> std::io::Result<()> {
+    let (meta_path, _) = paths(dir, &meta.url);
+    let tmp_meta = meta_path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_meta, serde_json::to_vec_pretty(meta)?).await?;
+    tokio::fs::rename(&tmp_meta, &meta_path).await
+}
+
+// Marks a cached copy as confirmed fresh after a 304
+pub async fn touch(meta: &mut CacheMeta) {
+    meta.fetched_at = Utc::now();
+    let Some(dir) = dir() else {
+        return;
+    };
+    if let Err(e) = write_meta(dir, meta).await {
+        warn!(url = %meta.url, error = %e, "Error writing cache entry");
+    }
+}
@@ -0,0 +1,254 @@
+use clap::{Args, Parser, Subcommand};
+use once_cell::sync::Lazy;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::export::ExportFormat;
+use crate::logging::LogFormat;
+use crate::notify::Sink;
+use crate::parser::{SharedHosts, Underscores};
+use crate::report::ReportFormat;
+
+// Parsed once and shared like the other global settings, every option can also be set from env.
+// Tests get the defaults, their arguments belong to the test harness
+pub static CLI: Lazy<Cli> = Lazy::new(|| {
+    if cfg!(test) {
+        Cli::parse_from([env!("CARGO_PKG_NAME")])
+    } else {
+        Cli::parse()
+    }
+});
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Sync ad-blocking lists to Cloudflare Gateway")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// File listing the block list sources, one URL or local path per line
+    #[arg(long, global = true, env = "LISTS_FILE", default_value = "lists.txt")]
+    pub lists: PathBuf,
+
+    /// File listing the whitelist sources, one URL or local path per line
+    #[arg(
+        long,
+        global = true,
+        env = "WHITELISTS_FILE",
+        default_value = "whitelists.txt"
+    )]
+    pub whitelists: PathBuf,
+
+    /// Keep downloaded sources in this directory and revalidate them with conditional requests
+    #[arg(long, global = true, env = "CACHE_DIR")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Build the lists from the cache only, without downloading anything
+    #[arg(long, global = true, env = "OFFLINE", requires = "cache_dir")]
+    pub offline: bool,
+
+    /// Largest size in MiB a source may download or inflate to
+    #[arg(
+        long,
+        global = true,
+        env = "MAX_DECOMPRESSED_MB",
+        default_value_t = 512
+    )]
+    pub max_decompressed_mb: u64,
+
+    /// Oldest cached copy a failing source may fall back to, e.g. "7d" or "36h"
+    #[arg(long, global = true, env = "CACHE_MAX_AGE", value_parser = humantime::parse_duration, default_value = "7d")]
+    pub cache_max_age: Duration,
+
+    /// Abort when more sources than this fail without a usable cached copy, per list file
+    #[arg(long, global = true, env = "MAX_FAILED_SOURCES")]
+    pub max_failed_sources: Option<usize>,
+
+    /// Abort when failed sources carry more than this percentage of the total weight, per list file
+    #[arg(long, global = true, env = "MAX_FAILED_WEIGHT", default_value_t = 10.0)]
+    pub max_failed_weight: f64,
+
+    /// Quarantine a source when more than this percentage of its last accepted domains disappear
+    #[arg(
+        long,
+        global = true,
+        env = "QUARANTINE_MAX_REMOVED",
+        default_value_t = 50.0
+    )]
+    pub quarantine_max_removed: f64,
+
+    /// Quarantine a source when it grows by more than this percentage of its last accepted domains
+    #[arg(
+        long,
+        global = true,
+        env = "QUARANTINE_MAX_ADDED",
+        default_value_t = 100.0
+    )]
+    pub quarantine_max_added: f64,
+
+    /// Sources whose last accepted copy is smaller than this skip the percentage checks
+    #[arg(
+        long,
+        global = true,
+        env = "QUARANTINE_MIN_DOMAINS",
+        default_value_t = 100
+    )]
+    pub quarantine_min_domains: usize,
+
+    /// Addresses that mark a hosts file line as a block, names pointed elsewhere are redirects
+    #[arg(
+        long,
+        global = true,
+        env = "SINKHOLE_ADDRESSES",
+        value_delimiter = ',',
+        default_value = "0.0.0.0,127.0.0.1,::,::1"
+    )]
+    pub sinkhole_addresses: Vec<IpAddr>,
+
+    /// What URL list sources do with hosts many unrelated users share, e.g. docs.google.com
+    #[arg(long, global = true, env = "SHARED_HOSTS", value_enum, default_value_t = SharedHosts::Skip)]
+    pub shared_hosts: SharedHosts,
+
+    /// Whether names with an underscore, e.g. `_dmarc.example.com`, are kept or counted as invalid
+    #[arg(long, global = true, env = "UNDERSCORES", value_enum, default_value_t = Underscores::Allow)]
+    pub underscores: Underscores,
+
+    /// Public Suffix List file to use instead of the bundled copy, e.g. a fresh public_suffix_list.dat
+    #[arg(long, global = true, env = "PUBLIC_SUFFIX_LIST")]
+    pub public_suffix_list: Option<PathBuf>,
+
+    /// Leave out the private section of the Public Suffix List, github.io or blogspot.com can then
+    /// be blocked like any other domain
+    #[arg(long, global = true, env = "ICANN_SUFFIXES_ONLY")]
+    pub icann_suffixes_only: bool,
+
+    /// STIX indicators and MISP attributes less confident than this, from 0 to 100, are left out
+    #[arg(long, global = true, env = "MIN_CONFIDENCE", value_parser = clap::value_parser!(u8).range(0..=100), default_value_t = 0)]
+    pub min_confidence: u8,
+
+    /// Take every source as downloaded this run, even when it looks anomalous
+    #[arg(long, global = true, env = "ACCEPT_ANOMALIES")]
+    pub accept_anomalies: bool,
+
+    /// Download the lists, print what became of every line of this source and exit without syncing
+    #[arg(long, global = true, env = "EXPLAIN_SOURCE")]
+    pub explain_source: Option<String>,
+
+    /// Sync attempts before giving up on a failing run, 0 retries forever
+    #[arg(long, global = true, env = "SYNC_MAX_ATTEMPTS", default_value_t = 5)]
+    pub max_attempts: u32,
+
+    /// Abort a sync that would add more than this many domains to the deployed lists
+    #[arg(long, global = true, env = "MAX_ADDED")]
+    pub max_added: Option<usize>,
+
+    /// Abort a sync that would grow the deployed lists by more than this percentage
+    #[arg(long, global = true, env = "MAX_ADDED_PERCENT")]
+    pub max_added_percent: Option<f64>,
+
+    /// Abort a sync that would remove more than this many deployed domains
+    #[arg(long, global = true, env = "MAX_REMOVED")]
+    pub max_removed: Option<usize>,
+
+    /// Abort a sync that would remove more than this percentage of the deployed domains
+    #[arg(long, global = true, env = "MAX_REMOVED_PERCENT", default_value = "50")]
+    pub max_removed_percent: Option<f64>,
+
+    /// Apply the sync even when it exceeds the change budget
+    #[arg(long, global = true, env = "FORCE")]
+    pub force: bool,
+
+    /// Serve Prometheus metrics on this address, e.g. "0.0.0.0:9184"
+    #[arg(long, global = true, env = "METRICS_ADDR")]
+    pub metrics_addr: Option<SocketAddr>,
+
+    /// Number of Gateway lists the Cloudflare account allows, reported as list capacity
+    #[arg(long, global = true, env = "CF_MAX_LISTS", default_value_t = 300)]
+    pub max_lists: u32,
+
+    /// Log verbosity, accepts RUST_LOG style directives such as "debug"
+    #[arg(long, global = true, env = "LOG_LEVEL", default_value = "info")]
+    pub log_level: String,
+
+    /// Log output format
+    #[arg(long, global = true, env = "LOG_FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// Write a JSON report of every run into this directory
+    #[arg(long, global = true, env = "REPORT_DIR")]
+    pub report_dir: Option<PathBuf>,
+
+    /// Extra rendered report formats written next to the JSON report
+    #[arg(
+        long,
+        global = true,
+        env = "REPORT_FORMAT",
+        value_enum,
+        value_delimiter = ','
+    )]
+    pub report_format: Vec<ReportFormat>,
+
+    /// Notification sink as kind[@when,...]=url, kind is webhook, slack, discord or telegram
+    /// and when is failure (default), changes or always. Repeat for several sinks
+    #[arg(long, global = true, env = "NOTIFY", value_delimiter = ' ')]
+    pub notify: Vec<Sink>,
+
+    /// Message template, placeholders: {status} {started_at} {duration} {allow} {block} {added}
+    /// {removed} {lists_created} {lists_deleted} {lists_failed} {policy_action} {error}
+    #[arg(long, global = true, env = "NOTIFY_TEMPLATE")]
+    pub notify_template: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a single sync and exit (default)
+    Sync,
+    /// Stay resident and run the sync on a schedule
+    Daemon(DaemonArgs),
+    /// Build the lists without touching Cloudflare and write them for other DNS servers
+    Export(ExportArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct DaemonArgs {
+    /// Cron expression in UTC, 5 fields (min hour dom mon dow) or 6 fields with seconds
+    #[arg(long, env = "SCHEDULE_CRON", conflicts_with = "interval")]
+    pub cron: Option<String>,
+
+    /// Fixed delay between the start of two runs, e.g. "6h" or "90m"
+    #[arg(long, env = "SCHEDULE_INTERVAL", value_parser = humantime::parse_duration)]
+    pub interval: Option<Duration>,
+
+    /// Random delay of up to this duration added before each run
+    #[arg(long, env = "SCHEDULE_JITTER", value_parser = humantime::parse_duration, default_value = "0s")]
+    pub jitter: Duration,
+
+    /// Also run once right after startup instead of waiting for the first tick
+    #[arg(long, env = "SCHEDULE_RUN_ON_START")]
+    pub run_on_start: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ExportArgs {
+    /// Output formats, comma separated
+    #[arg(
+        long,
+        env = "EXPORT_FORMAT",
+        value_enum,
+        value_delimiter = ',',
+        required = true
+    )]
+    pub format: Vec<ExportFormat>,
+
+    /// Directory the block-list.* and allow-list.* files are written to
+    #[arg(long, env = "EXPORT_DIR", default_value = ".")]
+    pub output_dir: PathBuf,
+
+    /// Leave out the comment header with generation time and counts
+    #[arg(long, env = "EXPORT_NO_HEADER")]
+    pub no_header: bool,
+
+    /// Address blocked names resolve to in hosts output
+    #[arg(long, env = "EXPORT_HOSTS_IP", default_value = "0.0.0.0")]
+    pub hosts_ip: std::net::IpAddr,
+}
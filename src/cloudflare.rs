@@ -1,7 +1,9 @@
 use once_cell::sync::Lazy;
 use reqwest::{header, Client};
-use serde_json;
 use std::time::Duration;
+use tracing::{error, instrument};
+
+use crate::metrics;
 
 static CF_API_TOKEN: Lazy<String> = Lazy::new(|| match std::env::var("CF_API_TOKEN") {
     Ok(token) => token,
@@ -24,10 +26,7 @@ static CLIENT: Lazy<Client> = Lazy::new(|| {
         Ok(value) => value,
         Err(e) => panic!("Error creating authorization header value: {}", e),
     };
-    headers.insert(
-        header::AUTHORIZATION,
-        header::HeaderValue::from(auth_header_value),
-    );
+    headers.insert(header::AUTHORIZATION, auth_header_value);
     headers.insert(
         header::CONNECTION,
         header::HeaderValue::from_static("keep-alive"),
@@ -43,20 +42,25 @@ static CLIENT: Lazy<Client> = Lazy::new(|| {
     }
 });
 
+#[instrument(level = "debug")]
 pub async fn get_cf_lists(prefix: &str) -> Option<Vec<serde_json::Value>> {
     let url = CLOUDFLARE_API_URL.to_string() + "/gateway/lists";
     let resp = match CLIENT.get(&url).send().await {
         Ok(resp) => resp,
         Err(e) => {
-            println!("Error sending request: {}", e);
+            metrics::record_cf_api("get_lists", None);
+            error!(error = %e, "Error sending request");
             return None;
         }
     };
     let status = resp.status();
+    metrics::record_cf_api("get_lists", Some(status));
     if status != 200 {
         match &resp.text().await {
-            Ok(body) => println!("Error response: {}, body: {}", status, body),
-            Err(e) => println!("Error response: {}, error reading body: {}", status, e),
+            Ok(body) => error!(status = status.as_u16(), body = %body, "Error response"),
+            Err(e) => {
+                error!(status = status.as_u16(), error = %e, "Error response, could not read body")
+            }
         }
         return None;
     }
@@ -64,25 +68,86 @@ pub async fn get_cf_lists(prefix: &str) -> Option<Vec<serde_json::Value>> {
         Ok(content) => content
             .get("result")
             .and_then(|result| result.as_array())
-            .and_then(|result_array| {
-                Some(
-                    result_array
-                        .iter()
-                        .filter_map(|line| match line["name"].as_str() {
-                            Some(name) if name.starts_with(prefix) => Some(line.to_owned()),
-                            _ => None,
-                        }),
-                )
+            .map(|result_array| {
+                result_array
+                    .iter()
+                    .filter_map(|line| match line["name"].as_str() {
+                        Some(name) if name.starts_with(prefix) => Some(line.to_owned()),
+                        _ => None,
+                    })
             })
-            .and_then(|result| Some(result.collect::<Vec<_>>())),
+            .map(|result| result.collect::<Vec<_>>()),
         Err(e) => {
-            println!("Error reading response: {}", e);
+            error!(error = %e, "Error reading response");
             return None;
         }
     };
-    return content;
+    content
 }
 
+// Lists hold at most 1000 items, but follow the pagination in case that ever changes
+#[instrument(level = "debug", skip_all, fields(list_id = id))]
+pub async fn get_cf_list_items(id: &str) -> Option<Vec<String>> {
+    let per_page = 1000;
+    let mut items = Vec::new();
+    for page in 1.. {
+        let url = format!(
+            "{}/gateway/lists/{id}/items?page={page}&per_page={per_page}",
+            CLOUDFLARE_API_URL.as_str()
+        );
+        let resp = match CLIENT.get(&url).send().await {
+            Ok(resp) => resp,
+            Err(e) => {
+                metrics::record_cf_api("get_list_items", None);
+                error!(error = %e, "Error sending request");
+                return None;
+            }
+        };
+        let status = resp.status();
+        metrics::record_cf_api("get_list_items", Some(status));
+        if status != 200 {
+            match &resp.text().await {
+                Ok(body) => error!(status = status.as_u16(), body = %body, "Error response"),
+                Err(e) => {
+                    error!(status = status.as_u16(), error = %e, "Error response, could not read body")
+                }
+            }
+            return None;
+        }
+        let content = match resp.json::<serde_json::Value>().await {
+            Ok(content) => content,
+            Err(e) => {
+                error!(error = %e, "Error reading response");
+                return None;
+            }
+        };
+        let page_items = item_values(&content);
+        let page_len = page_items.len();
+        items.extend(page_items);
+        let total = content["result_info"]["total_count"].as_u64();
+        if page_len < per_page || total.is_some_and(|total| items.len() as u64 >= total) {
+            break;
+        }
+    }
+    Some(items)
+}
+
+// Values of a page of list items. The API documents `result` as an array holding the array of
+// items, a flat array of items is read as well
+fn item_values(content: &serde_json::Value) -> Vec<String> {
+    content["result"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .flat_map(|item| match item.as_array() {
+            Some(items) => items.iter().collect(),
+            None => vec![item],
+        })
+        .filter_map(|item| item["value"].as_str().map(|v| v.to_owned()))
+        .collect()
+}
+
+#[instrument(skip(domains), fields(items = domains.len()))]
 pub async fn create_cf_list(name: String, domains: Vec<&String>) -> Option<serde_json::Value> {
     let url = CLOUDFLARE_API_URL.to_string() + "/gateway/lists";
     let resp = match CLIENT
@@ -101,103 +166,109 @@ pub async fn create_cf_list(name: String, domains: Vec<&String>) -> Option<serde
     {
         Ok(resp) => resp,
         Err(e) => {
-            println!("Error sending request: {}", e);
+            metrics::record_cf_api("create_list", None);
+            error!(error = %e, "Error sending request");
             return None;
         }
     };
     let status = resp.status();
+    metrics::record_cf_api("create_list", Some(status));
     if status != 200 {
         match &resp.text().await {
-            Ok(body) => println!("Error response: {}, body: {}", status, body),
-            Err(e) => println!("Error response: {}, error reading body: {}", status, e),
+            Ok(body) => error!(status = status.as_u16(), body = %body, "Error response"),
+            Err(e) => {
+                error!(status = status.as_u16(), error = %e, "Error response, could not read body")
+            }
         }
         return None;
     }
     let content = match resp.json::<serde_json::Value>().await {
-        Ok(content) => content
-            .get("result")
-            .and_then(|result| Some(result.to_owned())),
+        Ok(content) => content.get("result").map(|result| result.to_owned()),
         Err(e) => {
-            println!("Error reading response: {}", e);
+            error!(error = %e, "Error reading response");
             return None;
         }
     };
-    return content;
+    content
 }
 
+#[instrument(skip_all, fields(list_id = id))]
 pub async fn delete_cf_list(id: &str) -> Option<serde_json::Value> {
     let url = CLOUDFLARE_API_URL.to_string() + "/gateway/lists/" + id;
     let resp = match CLIENT.delete(&url).send().await {
         Ok(resp) => resp,
         Err(e) => {
-            println!("Error sending request: {}", e);
+            metrics::record_cf_api("delete_list", None);
+            error!(error = %e, "Error sending request");
             return None;
         }
     };
     let status = resp.status();
+    metrics::record_cf_api("delete_list", Some(status));
     if status != 200 {
         match &resp.text().await {
-            Ok(body) => println!("Error response: {}, body: {}", status, body),
-            Err(e) => println!("Error response: {}, error reading body: {}", status, e),
+            Ok(body) => error!(status = status.as_u16(), body = %body, "Error response"),
+            Err(e) => {
+                error!(status = status.as_u16(), error = %e, "Error response, could not read body")
+            }
         }
         return None;
     }
     let content = match resp.json::<serde_json::Value>().await {
-        Ok(content) => content
-            .get("result")
-            .and_then(|result| Some(result.to_owned())),
+        Ok(content) => content.get("result").map(|result| result.to_owned()),
         Err(e) => {
-            println!("Error reading response: {}", e);
+            error!(error = %e, "Error reading response");
             return None;
         }
     };
-    return content;
+    content
 }
 
+#[instrument(level = "debug")]
 pub async fn get_gateway_policies(prefix: &str) -> Option<Vec<serde_json::Value>> {
     let url = CLOUDFLARE_API_URL.to_string() + "/gateway/rules";
     let resp = match CLIENT.get(&url).send().await {
         Ok(resp) => resp,
         Err(e) => {
-            println!("Error sending request: {}", e);
+            metrics::record_cf_api("get_policies", None);
+            error!(error = %e, "Error sending request");
             return None;
         }
     };
     let status = resp.status();
+    metrics::record_cf_api("get_policies", Some(status));
     if status != 200 {
         match &resp.text().await {
-            Ok(body) => println!("Error response: {}, body: {}", status, body),
-            Err(e) => println!("Error response: {}, error reading body: {}", status, e),
+            Ok(body) => error!(status = status.as_u16(), body = %body, "Error response"),
+            Err(e) => {
+                error!(status = status.as_u16(), error = %e, "Error response, could not read body")
+            }
         }
         return None;
     }
     let content = match resp.json::<serde_json::Value>().await {
         Err(e) => {
-            println!("Error reading response: {}", e);
+            error!(error = %e, "Error reading response");
             return None;
         }
         Ok(content) => content
             .get("result")
             .and_then(|result| result.as_array())
-            .and_then(|result_array| {
-                Some(
-                    result_array
-                        .iter()
-                        .filter_map(|line| match line["name"].as_str() {
-                            Some(name) if name.starts_with(prefix) => Some(line.to_owned()),
-                            _ => None,
-                        }),
-                )
+            .map(|result_array| {
+                result_array
+                    .iter()
+                    .filter_map(|line| match line["name"].as_str() {
+                        Some(name) if name.starts_with(prefix) => Some(line.to_owned()),
+                        _ => None,
+                    })
             })
-            .and_then(|result| Some(result.collect::<Vec<_>>())),
+            .map(|result| result.collect::<Vec<_>>()),
     };
-    return content;
+    content
 }
 
-pub async fn create_gateway_policy(
-    name: &str,
-    list_ids: &Vec<String>,
-) -> Option<serde_json::Value> {
+#[instrument(skip(list_ids), fields(lists = list_ids.len()))]
+pub async fn create_gateway_policy(name: &str, list_ids: &[String]) -> Option<serde_json::Value> {
     let url = CLOUDFLARE_API_URL.to_string() + "/gateway/rules";
     let resp = match CLIENT
         .post(&url)
@@ -221,34 +292,37 @@ pub async fn create_gateway_policy(
     {
         Ok(resp) => resp,
         Err(e) => {
-            println!("Error sending request: {}", e);
+            metrics::record_cf_api("create_policy", None);
+            error!(error = %e, "Error sending request");
             return None;
         }
     };
     let status = resp.status();
+    metrics::record_cf_api("create_policy", Some(status));
     if status != 200 {
         match &resp.text().await {
-            Ok(body) => println!("Error response: {}, body: {}", status, body),
-            Err(e) => println!("Error response: {}, error reading body: {}", status, e),
+            Ok(body) => error!(status = status.as_u16(), body = %body, "Error response"),
+            Err(e) => {
+                error!(status = status.as_u16(), error = %e, "Error response, could not read body")
+            }
         }
         return None;
     }
     let content = match resp.json::<serde_json::Value>().await {
-        Ok(content) => content
-            .get("result")
-            .and_then(|result| Some(result.to_owned())),
+        Ok(content) => content.get("result").map(|result| result.to_owned()),
         Err(e) => {
-            println!("Error reading response: {}", e);
+            error!(error = %e, "Error reading response");
             return None;
         }
     };
-    return content;
+    content
 }
 
+#[instrument(skip(list_ids), fields(lists = list_ids.len()))]
 pub async fn update_gateway_policy(
     name: &str,
     policy_id: &str,
-    list_ids: &Vec<String>,
+    list_ids: &[String],
 ) -> Option<serde_json::Value> {
     let url = CLOUDFLARE_API_URL.to_string() + "/gateway/rules/" + policy_id;
     let resp = match CLIENT
@@ -272,37 +346,40 @@ pub async fn update_gateway_policy(
     {
         Ok(resp) => resp,
         Err(e) => {
-            println!("Error sending request: {}", e);
+            metrics::record_cf_api("update_policy", None);
+            error!(error = %e, "Error sending request");
             return None;
         }
     };
     let status = resp.status();
+    metrics::record_cf_api("update_policy", Some(status));
     if status != 200 {
         match &resp.text().await {
-            Ok(body) => println!("Error response: {}, body: {}", status, body),
-            Err(e) => println!("Error response: {}, error reading body: {}", status, e),
+            Ok(body) => error!(status = status.as_u16(), body = %body, "Error response"),
+            Err(e) => {
+                error!(status = status.as_u16(), error = %e, "Error response, could not read body")
+            }
         }
         return None;
     }
     let content = match resp.json::<serde_json::Value>().await {
-        Ok(content) => content
-            .get("result")
-            .and_then(|result| Some(result.to_owned())),
+        Ok(content) => content.get("result").map(|result| result.to_owned()),
         Err(e) => {
-            println!("Error reading response: {}", e);
+            error!(error = %e, "Error reading response");
             return None;
         }
     };
-    return content;
+    content
 }
 
+#[instrument]
 pub async fn delete_gateway_policy(prefix: &str) -> i32 {
     let policies = match get_gateway_policies(prefix).await {
         Some(policies) => policies,
         None => return 0,
     };
     let policy_id = match policies.first() {
-        Some(policy) => policy["id"].as_str().and_then(|x| Some(x.to_owned())),
+        Some(policy) => policy["id"].as_str().map(|x| x.to_owned()),
         None => return 0,
     };
     let policy_id_str = match policy_id {
@@ -312,21 +389,40 @@ pub async fn delete_gateway_policy(prefix: &str) -> i32 {
     let url = CLOUDFLARE_API_URL.to_string() + "/gateway/rules/" + &policy_id_str;
     let resp = match CLIENT.delete(&url).send().await {
         Ok(resp) => resp,
-        Err(e) => panic!("Error sending request: {}", e),
+        Err(e) => {
+            metrics::record_cf_api("delete_policy", None);
+            panic!("Error sending request: {}", e)
+        }
     };
     let status = resp.status();
+    metrics::record_cf_api("delete_policy", Some(status));
     if status != 200 {
         match &resp.text().await {
-            Ok(body) => println!("Error response: {}, body: {}", status, body),
-            Err(e) => println!("Error response: {}, error reading body: {}", status, e),
+            Ok(body) => error!(status = status.as_u16(), body = %body, "Error response"),
+            Err(e) => {
+                error!(status = status.as_u16(), error = %e, "Error response, could not read body")
+            }
         }
         return 0;
     }
     match resp.json::<serde_json::Value>().await {
-        Ok(content) => content
-            .get("result")
-            .and_then(|result| Some(result.to_owned())),
+        Ok(content) => content.get("result").map(|result| result.to_owned()),
         Err(e) => panic!("Error reading response: {}", e),
     };
-    return 1;
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn list_items_are_read_nested_or_flat() {
+        let nested = json!({"result": [[{"value": "a.example.com"}, {"value": "b.example.com"}]]});
+        assert_eq!(item_values(&nested), ["a.example.com", "b.example.com"]);
+        let flat = json!({"result": [{"value": "a.example.com"}, {"value": "b.example.com"}]});
+        assert_eq!(item_values(&flat), ["a.example.com", "b.example.com"]);
+        assert!(item_values(&json!({"result": null})).is_empty());
+    }
 }
@@ -0,0 +1,125 @@
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use rand::Rng;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+use crate::cli::DaemonArgs;
+
+enum Trigger {
+    Cron(Box<Schedule>),
+    Interval(Duration),
+}
+
+impl Trigger {
+    fn from_args(args: &DaemonArgs) -> Result<Self, String> {
+        match (&args.cron, args.interval) {
+            (Some(expr), _) => {
+                // Accept the classic 5 field crontab syntax, the cron crate wants seconds first
+                let expr = if expr.split_whitespace().count() == 5 {
+                    format!("0 {expr}")
+                } else {
+                    expr.to_owned()
+                };
+                Schedule::from_str(&expr)
+                    .map(|schedule| Trigger::Cron(Box::new(schedule)))
+                    .map_err(|e| format!("Invalid cron expression \"{expr}\": {e}"))
+            }
+            (None, Some(interval)) if !interval.is_zero() => Ok(Trigger::Interval(interval)),
+            _ => Err("Daemon mode needs either --cron or a non-zero --interval".to_owned()),
+        }
+    }
+
+    fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        match self {
+            Trigger::Cron(schedule) => schedule.after(&after).next(),
+            Trigger::Interval(interval) => {
+                Some(after + chrono::Duration::from_std(*interval).ok()?)
+            }
+        }
+    }
+}
+
+pub async fn run(args: &DaemonArgs) -> Result<(), String> {
+    let trigger = Trigger::from_args(args)?;
+    let mut current: Option<JoinHandle<()>> = None;
+
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    if args.run_on_start {
+        start_sync(&mut current);
+    }
+
+    let mut base = Utc::now();
+    loop {
+        let now = Utc::now();
+        // Anchor on the previous tick so intervals don't drift, unless we fell behind
+        let next = match trigger.next_after(base) {
+            Some(next) if next > now => Some(next),
+            _ => trigger.next_after(now),
+        };
+        let Some(next) = next else {
+            warn!("Schedule has no upcoming run, stopping daemon");
+            break;
+        };
+        let run_at = next + random_jitter(args.jitter);
+        info!(next_run = %run_at, "Next scheduled run");
+
+        let wait = (run_at - Utc::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = &mut shutdown => {
+                info!("Received shutdown signal");
+                break;
+            }
+        }
+        base = next;
+        start_sync(&mut current);
+    }
+
+    if let Some(handle) = current.filter(|handle| !handle.is_finished()) {
+        info!("Waiting for the running sync to finish");
+        let _ = handle.await;
+    }
+    Ok(())
+}
+
+fn start_sync(current: &mut Option<JoinHandle<()>>) {
+    if current.as_ref().is_some_and(|handle| !handle.is_finished()) {
+        warn!("Previous sync is still running, skipping this run");
+        return;
+    }
+    // A failed run is already logged and reported, the next tick simply tries again
+    *current = Some(tokio::spawn(async {
+        crate::sync().await;
+    }));
+}
+
+fn random_jitter(max: Duration) -> chrono::Duration {
+    if max.is_zero() {
+        return chrono::Duration::zero();
+    }
+    let millis = rand::thread_rng().gen_range(0..=max.as_millis() as i64);
+    chrono::Duration::milliseconds(millis)
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => error!(error = %e, "Error installing SIGTERM handler"),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
@@ -0,0 +1,324 @@
+use std::io::{self, Cursor, Read};
+use tracing::{debug, warn};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Xz,
+    Zstd,
+    Bzip2,
+    Zip,
+}
+
+// What the server or the file name claims about the body, used when its magic bytes show nothing
+#[derive(Clone, Debug, Default)]
+pub struct Hints {
+    pub content_type: Option<String>,
+    pub content_encoding: Option<String>,
+}
+
+impl Compression {
+    fn from_magic(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Some(Compression::Xz),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            [b'B', b'Z', b'h', b'1'..=b'9', ..] => Some(Compression::Bzip2),
+            [b'P', b'K', 0x03, 0x04, ..] => Some(Compression::Zip),
+            _ => None,
+        }
+    }
+
+    fn from_extension(name: &str) -> Option<Self> {
+        // Query strings and fragments are not part of the file name
+        let name = name.split(['?', '#']).next().unwrap_or_default();
+        let extension = name.rsplit_once('.')?.1.to_lowercase();
+        match extension.as_str() {
+            "gz" | "gzip" => Some(Compression::Gzip),
+            "xz" => Some(Compression::Xz),
+            "zst" | "zstd" => Some(Compression::Zstd),
+            "bz2" => Some(Compression::Bzip2),
+            "zip" => Some(Compression::Zip),
+            _ => None,
+        }
+    }
+
+    fn from_mime(value: &str) -> Option<Self> {
+        let value = value.split(';').next().unwrap_or_default().trim();
+        match value.to_lowercase().as_str() {
+            "gzip" | "x-gzip" | "application/gzip" | "application/x-gzip" => {
+                Some(Compression::Gzip)
+            }
+            "xz" | "application/x-xz" => Some(Compression::Xz),
+            "zstd" | "application/zstd" => Some(Compression::Zstd),
+            "bzip2" | "application/x-bzip2" => Some(Compression::Bzip2),
+            "application/zip" | "application/x-zip-compressed" => Some(Compression::Zip),
+            _ => None,
+        }
+    }
+
+    fn declared(name: &str, hints: &Hints) -> Option<Self> {
+        hints
+            .content_encoding
+            .as_deref()
+            .and_then(Compression::from_mime)
+            .or_else(|| {
+                hints
+                    .content_type
+                    .as_deref()
+                    .and_then(Compression::from_mime)
+            })
+            .or_else(|| Compression::from_extension(name))
+    }
+}
+
+// Why a body didn't inflate. Only a stream its decoder can't read may be plain text after all,
+// a body over the limit or a zip without the member asked for fails the source
+enum Failure {
+    Unreadable(String),
+    Refused(String),
+}
+
+impl Failure {
+    fn into_message(self) -> String {
+        match self {
+            Failure::Unreadable(message) | Failure::Refused(message) => message,
+        }
+    }
+}
+
+// The download is held to the same limit as the decompressed size
+pub fn exceeded(limit: u64) -> String {
+    format!("Size exceeds the limit of {limit} bytes")
+}
+
+// Bytes the magic numbers are read from
+static MAGIC_LEN: u64 = 6;
+
+// Bytes kept while a decoder picked from the headers or the extension reads the body. Decoders
+// give up on plain text within their first buffer, a body that fails later is taken as corrupt
+static RECORDED: usize = 1024 * 1024;
+
+// Stops reading one byte past the limit, a bomb never gets inflated further than that
+fn read_limited(reader: impl Read, limit: u64) -> Result<Vec<u8>, Failure> {
+    let mut out = Vec::new();
+    if let Err(e) = reader.take(limit + 1).read_to_end(&mut out) {
+        return Err(Failure::Unreadable(format!("Error decompressing: {}", e)));
+    }
+    if out.len() as u64 > limit {
+        return Err(Failure::Refused(exceeded(limit)));
+    }
+    Ok(out)
+}
+
+// Keeps the start of what a decoder reads, to hand the body back as is when it isn't compressed
+struct Recorder<R> {
+    inner: R,
+    raw: Vec<u8>,
+    overflowed: bool,
+}
+
+impl<R: Read> Read for Recorder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        if self.raw.len() + read <= RECORDED {
+            self.raw.extend_from_slice(&buf[..read]);
+        } else {
+            self.overflowed = true;
+        }
+        Ok(read)
+    }
+}
+
+fn unzip(raw: &[u8], member: Option<&str>, limit: u64) -> Result<Vec<u8>, Failure> {
+    let mut archive = match zip::ZipArchive::new(Cursor::new(raw)) {
+        Ok(archive) => archive,
+        Err(e) => {
+            return Err(Failure::Unreadable(format!(
+                "Error reading zip archive: {}",
+                e
+            )))
+        }
+    };
+    let name = match member {
+        Some(member) => member.to_owned(),
+        None => {
+            let files = archive
+                .file_names()
+                .filter_map(|name| name.ok())
+                .filter(|name| !name.ends_with('/'))
+                .map(|name| name.into_owned())
+                .collect::<Vec<_>>();
+            match files.as_slice() {
+                [name] => name.to_owned(),
+                _ => {
+                    return Err(Failure::Refused(format!(
+                        "Zip archive holds {} files, pick one with member=: {}",
+                        files.len(),
+                        files.join(", ")
+                    )))
+                }
+            }
+        }
+    };
+    let file = match archive.by_name(&name) {
+        Ok(file) => file,
+        Err(e) => {
+            return Err(Failure::Refused(format!(
+                "Error opening {} in zip archive: {}",
+                name, e
+            )))
+        }
+    };
+    debug!(member = %name, "Reading zip member");
+    read_limited(file, limit)
+}
+
+// The decoders read the body as it comes in, only the decompressed text is held in memory. A zip
+// is read from its central directory at the end, so the archive is held whole, up to the limit
+fn inflate(
+    compression: Compression,
+    reader: impl Read,
+    member: Option<&str>,
+    limit: u64,
+) -> Result<Vec<u8>, Failure> {
+    debug!(compression = ?compression, "Decompressing");
+    match compression {
+        Compression::Gzip => read_limited(flate2::read::MultiGzDecoder::new(reader), limit),
+        Compression::Xz => read_limited(xz2::read::XzDecoder::new_multi_decoder(reader), limit),
+        Compression::Zstd => match zstd::stream::read::Decoder::new(reader) {
+            Ok(decoder) => read_limited(decoder, limit),
+            Err(e) => Err(Failure::Unreadable(format!("Error decompressing: {}", e))),
+        },
+        Compression::Bzip2 => read_limited(bzip2::read::MultiBzDecoder::new(reader), limit),
+        Compression::Zip => unzip(&read_limited(reader, limit)?, member, limit),
+    }
+}
+
+// The magic bytes decide, the headers and the extension pick the decoder when there are none,
+// e.g. for a zip with a stub in front or zstd opening with a skippable frame. A body declared
+// compressed that its decoder can't read is taken as is, servers often label plain text wrong.
+// The body is read as it comes in, the caller caps its size
+pub fn decode(
+    name: &str,
+    mut reader: impl Read,
+    hints: &Hints,
+    member: Option<&str>,
+    limit: u64,
+) -> Result<Vec<u8>, String> {
+    let mut head = Vec::new();
+    if let Err(e) = reader.by_ref().take(MAGIC_LEN).read_to_end(&mut head) {
+        return Err(format!("Error reading source: {}", e));
+    }
+    let magic = Compression::from_magic(&head);
+    let body = Cursor::new(head).chain(reader);
+    if let Some(compression) = magic {
+        return inflate(compression, body, member, limit).map_err(Failure::into_message);
+    }
+    let Some(declared) = Compression::declared(name, hints) else {
+        return read_limited(body, limit).map_err(Failure::into_message);
+    };
+    let not_compressed = |e: &str| warn!(declared = ?declared, error = %e, "Source is declared compressed but isn't, reading it as is");
+    // A zip is held whole anyway, the body is at hand when it isn't one
+    if declared == Compression::Zip {
+        let raw = read_limited(body, limit).map_err(Failure::into_message)?;
+        return match unzip(&raw, member, limit) {
+            Err(Failure::Unreadable(e)) => {
+                not_compressed(&e);
+                Ok(raw)
+            }
+            result => result.map_err(Failure::into_message),
+        };
+    }
+    let mut recorder = Recorder {
+        inner: body,
+        raw: Vec::new(),
+        overflowed: false,
+    };
+    match inflate(declared, &mut recorder, member, limit) {
+        Err(Failure::Unreadable(e)) if !recorder.overflowed => {
+            not_compressed(&e);
+            let Recorder { inner, mut raw, .. } = recorder;
+            // The rest of the body the decoder didn't get to
+            let rest = (limit + 1).saturating_sub(raw.len() as u64);
+            if let Err(e) = inner.take(rest).read_to_end(&mut raw) {
+                return Err(format!("Error reading source: {}", e));
+            }
+            if raw.len() as u64 > limit {
+                return Err(exceeded(limit));
+            }
+            Ok(raw)
+        }
+        result => result.map_err(Failure::into_message),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn gzip(body: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(body).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    // Hands out a few bytes per read, as a download does
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(self.0.len()).min(3);
+            buf[..len].copy_from_slice(&self.0[..len]);
+            self.0 = &self.0[len..];
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn bodies_inflate_as_they_are_read() {
+        let raw = gzip(b"a.example.com\nb.example.com\n");
+        let body = decode("list", Trickle(&raw), &Hints::default(), None, 1024).unwrap();
+        assert_eq!(body, b"a.example.com\nb.example.com\n");
+        let plain = decode(
+            "list.txt",
+            Trickle(b"a.example.com\n"),
+            &Hints::default(),
+            None,
+            1024,
+        );
+        assert_eq!(plain.unwrap(), b"a.example.com\n");
+    }
+
+    #[test]
+    fn inflating_stops_past_the_limit() {
+        let raw = gzip(&[b'a'; 4096]);
+        assert_eq!(
+            decode("list.gz", &raw[..], &Hints::default(), None, 1024),
+            Err(exceeded(1024))
+        );
+        assert_eq!(
+            decode("list.txt", &[b'a'; 2048][..], &Hints::default(), None, 1024),
+            Err(exceeded(1024))
+        );
+    }
+
+    #[test]
+    fn plain_text_declared_compressed_is_read_as_is() {
+        let hints = Hints {
+            content_encoding: Some("gzip".to_owned()),
+            ..Hints::default()
+        };
+        let body = decode("list", Trickle(b"a.example.com\n"), &hints, None, 1024);
+        assert_eq!(body.unwrap(), b"a.example.com\n");
+        let body = decode(
+            "list.zip",
+            &b"a.example.com\n"[..],
+            &Hints::default(),
+            None,
+            1024,
+        );
+        assert_eq!(body.unwrap(), b"a.example.com\n");
+    }
+}
@@ -0,0 +1,93 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+use tracing::debug;
+
+// Bytes looked at to tell UTF-16 without a BOM from single byte encodings
+static SNIFF_BYTES: usize = 4096;
+
+// Text of a source ready for the parsers, with what was done to get it
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static str,
+    // Set when the body had to be repaired or its encoding guessed, reported with the source
+    pub issue: Option<String>,
+}
+
+// `text/plain; charset=utf-16` names the encoding, unknown labels are ignored
+fn charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case("charset") {
+            return None;
+        }
+        Encoding::for_label(value.trim().trim_matches('"').as_bytes())
+    })
+}
+
+// Text in UTF-16 has a NUL in most ASCII code units, on the odd bytes for little endian
+fn sniff_utf16(raw: &[u8]) -> Option<&'static Encoding> {
+    let sample = &raw[..raw.len().min(SNIFF_BYTES) & !1];
+    let pairs = sample.len() / 2;
+    if pairs == 0 {
+        return None;
+    }
+    let (mut even, mut odd) = (0, 0);
+    for pair in sample.chunks_exact(2) {
+        even += (pair[0] == 0) as usize;
+        odd += (pair[1] == 0) as usize;
+    }
+    if odd * 10 > pairs * 3 && even * 10 < pairs {
+        Some(UTF_16LE)
+    } else if even * 10 > pairs * 3 && odd * 10 < pairs {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+// The BOM wins, then the encoding set for the source, the charset the server declared and last
+// a guess: UTF-16 by its NULs, UTF-8 when the body is valid UTF-8, windows-1252 otherwise.
+// Line endings end up as \n and NUL bytes are dropped
+pub fn decode(
+    raw: &[u8],
+    forced: Option<&'static Encoding>,
+    content_type: Option<&str>,
+) -> Decoded {
+    let mut issues = Vec::new();
+    let (encoding, body) = match Encoding::for_bom(raw) {
+        Some((encoding, bom)) => (encoding, &raw[bom..]),
+        None => {
+            let declared = forced.or_else(|| content_type.and_then(charset));
+            // ASCII in UTF-16 is valid UTF-8 too, its NULs have to be looked at first
+            let encoding = match declared.or_else(|| sniff_utf16(raw)) {
+                Some(encoding) => encoding,
+                None if std::str::from_utf8(raw).is_ok() => UTF_8,
+                None => {
+                    issues.push(format!("Not valid UTF-8, read as {}", WINDOWS_1252.name()));
+                    WINDOWS_1252
+                }
+            };
+            (encoding, raw)
+        }
+    };
+    debug!(encoding = encoding.name(), "Decoding source");
+    let (text, had_errors) = encoding.decode_without_bom_handling(body);
+    if had_errors {
+        issues.push(format!("Malformed {} sequences replaced", encoding.name()));
+    }
+    let nul = text.matches('\0').count();
+    if nul > 0 {
+        issues.push(format!("{nul} NUL characters removed"));
+    }
+    let text = if nul > 0 || text.contains('\r') {
+        text.replace("\r\n", "\n")
+            .replace('\r', "\n")
+            .replace('\0', "")
+    } else {
+        text.into_owned()
+    };
+    Decoded {
+        text,
+        encoding: encoding.name(),
+        issue: (!issues.is_empty()).then(|| issues.join(", ")),
+    }
+}
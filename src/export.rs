@@ -0,0 +1,244 @@
+use chrono::Utc;
+use clap::ValueEnum;
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::net::IpAddr;
+use tracing::info;
+
+use crate::cli::ExportArgs;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ExportFormat {
+    /// One domain per line
+    Plain,
+    /// `0.0.0.0 domain`, only exact names are blocked so subdomains stay listed
+    Hosts,
+    /// `address=/domain/`, allow list as `server=/domain/#`
+    Dnsmasq,
+    /// `local-zone: "domain." always_nxdomain`, allow list as transparent zones
+    Unbound,
+    /// BIND response policy zone, allow list as rpz-passthru
+    Rpz,
+    /// AdGuard / ABP `||domain^`, allow list as `@@|domain^`
+    Adguard,
+    /// Pi-hole adlist, one domain per line matched exactly, redirects in `custom.list` form
+    Pihole,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Block,
+    Allow,
+    // Names hosts sources point at an address other than a sinkhole
+    Redirect,
+}
+
+impl ExportFormat {
+    fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Plain => "txt",
+            ExportFormat::Hosts => "hosts",
+            ExportFormat::Dnsmasq => "dnsmasq.conf",
+            ExportFormat::Unbound => "unbound.conf",
+            ExportFormat::Rpz => "rpz.zone",
+            ExportFormat::Adguard => "adguard.txt",
+            ExportFormat::Pihole => "pihole.txt",
+        }
+    }
+
+    // Formats whose readers block only the name listed, not its subdomains
+    fn exact(&self) -> bool {
+        matches!(self, ExportFormat::Hosts | ExportFormat::Pihole)
+    }
+
+    fn comment(&self) -> &'static str {
+        match self {
+            ExportFormat::Rpz => ";",
+            ExportFormat::Adguard => "!",
+            _ => "#",
+        }
+    }
+
+    // None when the format has nothing to express this kind of entry with. The address is the
+    // hosts IP for blocks and the target of a redirect
+    fn line(&self, kind: Kind, domain: &str, ip: &IpAddr) -> Option<String> {
+        // Validated names never contain these, skip rather than emit a line that breaks the file
+        if domain.is_empty() || domain.contains(|c: char| c.is_whitespace() || c.is_control()) {
+            return None;
+        }
+        match (self, kind) {
+            (ExportFormat::Plain, Kind::Redirect) => None,
+            (ExportFormat::Plain, _) => Some(domain.to_owned()),
+            (ExportFormat::Hosts, Kind::Block | Kind::Redirect) => {
+                (!domain.contains('#')).then(|| format!("{ip} {domain}"))
+            }
+            (ExportFormat::Hosts, Kind::Allow) => None,
+            (ExportFormat::Dnsmasq, kind) => {
+                if domain.contains(['/', '#']) {
+                    return None;
+                }
+                Some(match kind {
+                    Kind::Block => format!("address=/{domain}/"),
+                    Kind::Allow => format!("server=/{domain}/#"),
+                    Kind::Redirect => format!("address=/{domain}/{ip}"),
+                })
+            }
+            (ExportFormat::Unbound, kind) => {
+                let name = domain.replace('\\', "\\\\").replace('"', "\\\"");
+                Some(match kind {
+                    Kind::Block => format!("local-zone: \"{name}.\" always_nxdomain"),
+                    Kind::Allow => format!("local-zone: \"{name}.\" transparent"),
+                    Kind::Redirect => format!("local-data: \"{name}. {} {ip}\"", record_type(ip)),
+                })
+            }
+            (ExportFormat::Rpz, kind) => {
+                let name = escape_zone_name(domain);
+                // The apex and the wildcard are separate owners in RPZ, allow entries are exact names
+                Some(match kind {
+                    Kind::Block => format!("{name} CNAME .\n*.{name} CNAME ."),
+                    Kind::Allow => format!("{name} CNAME rpz-passthru."),
+                    Kind::Redirect => format!("{name} {} {ip}", record_type(ip)),
+                })
+            }
+            (ExportFormat::Adguard, kind) => {
+                if domain.contains(['|', '^', '$', '*', '/']) {
+                    return None;
+                }
+                Some(match kind {
+                    Kind::Block => format!("||{domain}^"),
+                    Kind::Allow => format!("@@|{domain}^"),
+                    Kind::Redirect => format!("|{domain}^$dnsrewrite={ip}"),
+                })
+            }
+            (ExportFormat::Pihole, Kind::Block | Kind::Allow) => Some(domain.to_owned()),
+            (ExportFormat::Pihole, Kind::Redirect) => Some(format!("{ip} {domain}")),
+        }
+    }
+
+    fn preamble(&self) -> Option<String> {
+        match self {
+            ExportFormat::Rpz => Some(format!(
+                "$TTL 300\n@ SOA localhost. root.localhost. {} 3600 600 86400 300\n  NS localhost.",
+                Utc::now().timestamp()
+            )),
+            ExportFormat::Unbound => Some("server:".to_owned()),
+            _ => None,
+        }
+    }
+
+    fn render(
+        &self,
+        kind: Kind,
+        domains: &HashMap<&String, IpAddr>,
+        args: &ExportArgs,
+        sources: usize,
+    ) -> Option<String> {
+        let lines = domains
+            .iter()
+            .sorted()
+            .filter_map(|(domain, ip)| self.line(kind, domain, ip))
+            .collect::<Vec<_>>();
+        if lines.is_empty() && !domains.is_empty() {
+            return None;
+        }
+        let mut out = String::new();
+        if !args.no_header {
+            let comment = self.comment();
+            let title = match kind {
+                Kind::Block => "Block list",
+                Kind::Allow => "Allow list",
+                Kind::Redirect => "Redirects",
+            };
+            let _ = writeln!(
+                out,
+                "{comment} {title} generated by cloudflare_gateway_pihole"
+            );
+            let _ = writeln!(out, "{comment} Generated: {}", Utc::now().to_rfc3339());
+            let _ = writeln!(out, "{comment} Domains: {}", domains.len());
+            let _ = writeln!(out, "{comment} Sources: {sources}");
+            let _ = writeln!(out, "{comment}");
+        }
+        if let Some(preamble) = self.preamble() {
+            let _ = writeln!(out, "{preamble}");
+        }
+        let indent = if *self == ExportFormat::Unbound {
+            "  "
+        } else {
+            ""
+        };
+        for line in lines {
+            let _ = writeln!(out, "{indent}{line}");
+        }
+        Some(out)
+    }
+}
+
+fn record_type(ip: &IpAddr) -> &'static str {
+    match ip {
+        IpAddr::V4(_) => "A",
+        IpAddr::V6(_) => "AAAA",
+    }
+}
+
+// Characters with a meaning in zone files are escaped with a backslash
+fn escape_zone_name(domain: &str) -> String {
+    let mut escaped = String::with_capacity(domain.len());
+    for c in domain.chars() {
+        if matches!(c, ';' | '(' | ')' | '"' | '$' | '@' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn with_ip<'a>(
+    domains: impl IntoIterator<Item = &'a String>,
+    ip: IpAddr,
+) -> HashMap<&'a String, IpAddr> {
+    domains.into_iter().map(|domain| (domain, ip)).collect()
+}
+
+// The block list comes collapsed for Gateway, the subdomains it dropped are put back for formats
+// that match exact names
+pub async fn write(
+    args: &ExportArgs,
+    block: &HashSet<String>,
+    subdomains: &HashSet<String>,
+    allow: &HashSet<String>,
+    overrides: &HashMap<String, IpAddr>,
+    sources: usize,
+) -> std::io::Result<()> {
+    tokio::fs::create_dir_all(&args.output_dir).await?;
+    let exact_block = if args.format.iter().any(ExportFormat::exact) {
+        with_ip(block.iter().chain(subdomains), args.hosts_ip)
+    } else {
+        HashMap::new()
+    };
+    let block = with_ip(block, args.hosts_ip);
+    let allow = with_ip(allow, args.hosts_ip);
+    let overrides = overrides.iter().map(|(domain, ip)| (domain, *ip)).collect();
+    for format in args.format.iter().unique() {
+        let block = if format.exact() { &exact_block } else { &block };
+        for (kind, name, domains) in [
+            (Kind::Block, "block-list", block),
+            (Kind::Allow, "allow-list", &allow),
+            (Kind::Redirect, "overrides", &overrides),
+        ] {
+            // Most runs have no redirects, leave the file out rather than write an empty one
+            if kind == Kind::Redirect && domains.is_empty() {
+                continue;
+            }
+            let Some(content) = format.render(kind, domains, args, sources) else {
+                continue;
+            };
+            let path = args
+                .output_dir
+                .join(format!("{name}.{}", format.extension()));
+            tokio::fs::write(&path, content).await?;
+            info!(path = %path.display(), domains = domains.len(), "Exported list");
+        }
+    }
+    Ok(())
+}
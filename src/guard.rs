@@ -0,0 +1,100 @@
+use itertools::Itertools;
+use std::error::Error;
+use std::fmt;
+
+use crate::cli;
+use crate::report::{RunReport, SourceStatus};
+
+// Sources listed when explaining a swing, the biggest movers first
+static EXPLAINED_SOURCES: usize = 5;
+
+// A sync stopped by the change budget, retrying would build the same lists and stop again
+#[derive(Debug)]
+pub struct BudgetExceeded(pub String);
+
+impl fmt::Display for BudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for BudgetExceeded {}
+
+fn exceeds(count: usize, deployed: u64, max: Option<usize>, max_percent: Option<f64>) -> bool {
+    let over_count = max.is_some_and(|max| count > max);
+    // A first deploy has nothing to compare percentages with
+    let over_percent =
+        deployed > 0 && max_percent.is_some_and(|max| count as f64 / deployed as f64 * 100.0 > max);
+    over_count || over_percent
+}
+
+// Sources whose domain count moved the most since their last accepted copy
+fn explain(report: &RunReport) -> String {
+    let swings = report
+        .sources
+        .iter()
+        .filter_map(|source| {
+            let previous = source.previous_domains?;
+            let swing = source.domains.abs_diff(previous);
+            (swing > 0 || source.status != SourceStatus::Ok).then_some((source, previous, swing))
+        })
+        .sorted_by(|a, b| b.2.cmp(&a.2))
+        .take(EXPLAINED_SOURCES)
+        .map(|(source, previous, _)| {
+            format!(
+                "{} {}: {} -> {} ({})",
+                source.list,
+                source.url,
+                previous,
+                source.domains,
+                source.status.as_str()
+            )
+        })
+        .collect::<Vec<_>>();
+    if swings.is_empty() {
+        // Counts of the last accepted copies live in the cache directory
+        if report
+            .sources
+            .iter()
+            .all(|source| source.previous_domains.is_none())
+        {
+            return "unknown, no source has an accepted copy to compare with, set --cache-dir to keep them".to_owned();
+        }
+        return "no source changed against its last accepted copy".to_owned();
+    }
+    swings.join("; ")
+}
+
+// Checks the planned changes against the budget, Err explains why the sync must not go ahead
+pub fn check(
+    report: &RunReport,
+    added: usize,
+    removed: usize,
+    deployed: u64,
+) -> Result<(), String> {
+    let mut exceeded = Vec::new();
+    if exceeds(
+        added,
+        deployed,
+        cli::CLI.max_added,
+        cli::CLI.max_added_percent,
+    ) {
+        exceeded.push(format!("{added} domains added"));
+    }
+    if exceeds(
+        removed,
+        deployed,
+        cli::CLI.max_removed,
+        cli::CLI.max_removed_percent,
+    ) {
+        exceeded.push(format!("{removed} domains removed"));
+    }
+    if exceeded.is_empty() {
+        return Ok(());
+    }
+    Err(format!(
+        "Change budget exceeded with {} of {deployed} deployed. Largest source swings: {}",
+        exceeded.join(" and "),
+        explain(report)
+    ))
+}
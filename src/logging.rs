@@ -0,0 +1,36 @@
+use clap::ValueEnum;
+use std::io::IsTerminal;
+use tracing_subscriber::EnvFilter;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+// `level` takes the same directives as RUST_LOG, e.g. "debug" or "info,cloudflare_gateway_pihole=trace"
+pub fn init(level: &str, format: LogFormat) {
+    let filter = match EnvFilter::try_new(level) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!(
+                "Invalid log level \"{}\": {}, falling back to info",
+                level, e
+            );
+            EnvFilter::new("info")
+        }
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_target(false)
+        .with_ansi(std::io::stdout().is_terminal());
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(true)
+            .init(),
+    }
+}
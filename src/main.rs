@@ -1,66 +1,353 @@
 use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use tracing::{error, info, info_span, instrument, warn, Instrument};
 
+use report::{RunReport, RunStatus};
+
+mod cache;
+mod cli;
 mod cloudflare;
+mod daemon;
+mod decompress;
+mod encoding;
+mod export;
+mod guard;
+mod logging;
+mod metrics;
+mod notify;
+mod parser;
+mod quarantine;
+mod report;
+mod source;
+mod suffix;
 mod utils;
 
 static SLEEP_TIME_SEC: u64 = 4;
 
 #[tokio::main]
 async fn main() {
+    logging::init(&cli::CLI.log_level, cli::CLI.log_format);
+    metrics::init(cli::CLI.max_lists);
+    if let Some(addr) = cli::CLI.metrics_addr {
+        tokio::spawn(metrics::serve(addr));
+    }
+    if let Some(url) = &cli::CLI.explain_source {
+        if !explain(url).await {
+            std::process::exit(1);
+        }
+        return;
+    }
+    match &cli::CLI.command {
+        None | Some(cli::Command::Sync) => {
+            if !sync().await {
+                std::process::exit(1);
+            }
+        }
+        Some(cli::Command::Export(args)) => {
+            if let Err(e) = export(args).await {
+                error!(error = %e, "Export failed");
+                std::process::exit(1);
+            }
+        }
+        Some(cli::Command::Daemon(args)) => {
+            if let Err(e) = daemon::run(args).await {
+                error!(error = %e, "Daemon failed to start");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+// Retries a failing run up to --max-attempts times, returns whether it succeeded in the end
+pub async fn sync() -> bool {
     let mut is_done = false;
+    let mut notified_failure = false;
+    let mut attempt = 0;
+    let mut is_final = false;
     while !is_done {
-        match exec().await {
+        attempt += 1;
+        let mut report = RunReport::new();
+        match exec(&mut report).await {
             Ok(_) => {
-                println!("Done!");
+                info!("Done!");
+                metrics::SYNC_RUNS.with_label_values(&["success"]).inc();
+                metrics::LAST_SUCCESS.set(chrono::Utc::now().timestamp() as f64);
+                let status = match report.status {
+                    RunStatus::Unchanged => RunStatus::Unchanged,
+                    _ => RunStatus::Success,
+                };
+                report.finish(status, None);
                 is_done = true;
             }
             Err(e) => {
-                println!("Error: {}", e);
-                tokio::time::sleep(tokio::time::Duration::from_secs(SLEEP_TIME_SEC)).await;
+                error!(error = %e, attempt, "Sync failed");
+                metrics::SYNC_RUNS.with_label_values(&["failure"]).inc();
+                report.finish(RunStatus::Failure, Some(e.to_string()));
+                is_final = is_final_error(e.as_ref());
+            }
+        }
+        if let Some(dir) = &cli::CLI.report_dir {
+            report.write(dir, &cli::CLI.report_format).await;
+        }
+        // Retries would repeat the same failure every few seconds, only tell once per sync
+        if report.status != RunStatus::Failure || !notified_failure {
+            let template = cli::CLI
+                .notify_template
+                .as_deref()
+                .unwrap_or(notify::DEFAULT_TEMPLATE);
+            notify::notify(&cli::CLI.notify, template, &report).await;
+            notified_failure |= report.status == RunStatus::Failure;
+        }
+        if !is_done {
+            // The same lists would exceed the budget again, only a change or --force gets past it
+            if is_final {
+                error!("Not retrying, the change budget was exceeded");
+                return false;
             }
+            if cli::CLI.max_attempts != 0 && attempt >= cli::CLI.max_attempts {
+                error!(attempts = attempt, "Giving up on sync");
+                return false;
+            }
+            info!("Retrying in {SLEEP_TIME_SEC}s");
+            tokio::time::sleep(tokio::time::Duration::from_secs(SLEEP_TIME_SEC)).await;
         }
     }
+    true
 }
 
-async fn exec() -> Result<(), Box<dyn Error>> {
-    let white_list = utils::read_file_content_and_download("whitelists.txt", true, None).await;
-    let temp_list =
-        utils::read_file_content_and_download("lists.txt", false, Some(white_list)).await;
-    let black_list = temp_list.iter().sorted().collect::<Vec<_>>();
+// Errors a retry would only repeat
+fn is_final_error(e: &(dyn Error + Send + Sync + 'static)) -> bool {
+    e.is::<guard::BudgetExceeded>()
+}
 
-    println!("Black list size: {}", black_list.len());
+// What a run builds from the list files
+struct Lists {
+    allow: HashSet<String>,
+    block: HashSet<String>,
+    // Blocked names collapsed under a blocked ancestor, for exports matching exact names
+    subdomains: HashSet<String>,
+    // Names hosts sources point at a real address
+    overrides: HashMap<String, IpAddr>,
+    // New accepted copies of the sources, put in place by `quarantine::commit` once the run went through
+    staged: Vec<PathBuf>,
+}
 
-    // match tokio::fs::write("block_list.txt", black_list.iter().join("\n")).await {
-    //     Ok(_) => println!("Wrote {} block list to file", black_list.len()),
-    //     Err(e) => println!("Error writing block list to file: {}", e),
-    // }
-    // return Ok(());
+// Downloads and aggregates both lists
+async fn collect_lists(report: &mut RunReport) -> Result<Lists, String> {
+    let (white_list, mut staged) = async {
+        let timer = metrics::time_phase("whitelist");
+        let white_list =
+            utils::read_file_content_and_download(&cli::CLI.whitelists, true, None).await;
+        report.sources.extend(white_list.sources);
+        report
+            .durations
+            .insert("whitelist".to_owned(), timer.finish());
+        match white_list.failure {
+            Some(failure) => Err(failure),
+            None => Ok((white_list.domains, white_list.staged)),
+        }
+    }
+    .instrument(info_span!("phase", name = "whitelist"))
+    .await?;
+    let mut white_list = white_list;
+
+    let (temp_list, subdomains, exceptions, overrides) = async {
+        let timer = metrics::time_phase("blocklist");
+        let temp_list =
+            utils::read_file_content_and_download(&cli::CLI.lists, false, Some(&white_list)).await;
+        report.totals.whitelisted = temp_list.whitelisted;
+        report.totals.collapsed_subdomains = temp_list.collapsed;
+        report.totals.public_suffixes = temp_list.public_suffixes;
+        report.totals.exceptions = temp_list.exceptions;
+        report.totals.carve_outs = temp_list.carve_outs;
+        report.totals.excepted = temp_list.excepted;
+        report.totals.shadowed_exceptions = temp_list.shadowed_exceptions;
+        report.totals.shadowed_carve_outs = temp_list.shadowed_carve_outs;
+        report.totals.overrides = temp_list.overrides.len();
+        report.sources.extend(temp_list.sources);
+        staged.extend(temp_list.staged);
+        report
+            .durations
+            .insert("blocklist".to_owned(), timer.finish());
+        match temp_list.failure {
+            Some(failure) => Err(failure),
+            None => Ok((
+                temp_list.domains,
+                temp_list.subdomains,
+                temp_list.allow,
+                temp_list.overrides,
+            )),
+        }
+    }
+    .instrument(info_span!("phase", name = "blocklist"))
+    .await?;
+    // Exception rules of the block list sources apply to this run like whitelist entries
+    white_list.extend(exceptions);
+    report.totals.allow = white_list.len();
+    metrics::DOMAINS_TOTAL
+        .with_label_values(&["allow"])
+        .set(white_list.len() as i64);
+    info!(size = white_list.len(), "White list size");
+    report.totals.block = temp_list.len();
+    report.totals.skipped_rules = report
+        .sources
+        .iter()
+        .map(|source| source.skipped_rules)
+        .sum();
+    metrics::DOMAINS_TOTAL
+        .with_label_values(&["block"])
+        .set(temp_list.len() as i64);
+    info!(size = temp_list.len(), "Black list size");
+
+    Ok(Lists {
+        allow: white_list,
+        block: temp_list,
+        subdomains,
+        overrides,
+        staged,
+    })
+}
+
+// Prints the outcomes of the source as listed in the list files, false when no list has it.
+// A failure budget being exceeded doesn't stop the diagnosis, it's often why it's run
+async fn explain(url: &str) -> bool {
+    let mut report = RunReport::new();
+    if let Err(e) = collect_lists(&mut report).await {
+        warn!(error = %e, "Lists failed to build");
+    }
+    let mut found = false;
+    for source in report.sources.iter().filter(|source| source.url == url) {
+        print!("{}", source.explain());
+        found = true;
+    }
+    if !found {
+        error!(source_url = url, "Source not found in the list files");
+    }
+    found
+}
+
+#[instrument(name = "export", skip_all)]
+async fn export(args: &cli::ExportArgs) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut report = RunReport::new();
+    let lists = collect_lists(&mut report).await?;
+    let sources = report.sources.len();
+    export::write(
+        args,
+        &lists.block,
+        &lists.subdomains,
+        &lists.allow,
+        &lists.overrides,
+        sources,
+    )
+    .await?;
+    quarantine::commit(&lists.staged).await;
+    Ok(())
+}
+
+// The sources only get new accepted copies when the sync went through, a failed or aborted one
+// compares against the same copies when it's run again
+#[instrument(name = "sync", skip_all)]
+async fn exec(report: &mut RunReport) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let lists = collect_lists(report).await?;
+    deploy(report, &lists.block, &lists.overrides).await?;
+    quarantine::commit(&lists.staged).await;
+    Ok(())
+}
+
+// Deployed lists that can't be read are a plain error, the Cloudflare API failing for a moment
+// shouldn't stop the retries. Only a real budget overrun is final
+fn check_budget(
+    report: &RunReport,
+    changes: Option<(usize, usize)>,
+    deployed: Option<u64>,
+    force: bool,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (Some((added, removed)), Some(count)) = (changes, deployed) else {
+        if !force {
+            return Err("Deployed lists could not be read".into());
+        }
+        warn!("Deployed lists could not be read, going ahead without the change budget, --force is set");
+        return Ok(());
+    };
+    if let Err(e) = guard::check(report, added, removed, count) {
+        if !force {
+            return Err(guard::BudgetExceeded(format!("{e}. Rerun with --force to apply")).into());
+        }
+        warn!(reason = %e, "Going ahead despite the change budget, --force is set");
+    }
+    Ok(())
+}
+
+async fn deploy(
+    report: &mut RunReport,
+    temp_list: &HashSet<String>,
+    overrides: &HashMap<String, IpAddr>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if !overrides.is_empty() {
+        warn!(
+            count = overrides.len(),
+            "Redirected names can't be synced to Gateway lists, export writes them out"
+        );
+    }
+    let black_list = temp_list.iter().sorted().collect::<Vec<_>>();
 
     let cf_prefix = "[AdBlock-DNS Block List]";
     let cf_lists = cloudflare::get_cf_lists(cf_prefix).await;
     let cf_lists_len = cf_lists.as_ref().map_or_else(|| 0, |l| l.len());
-    println!("Cloudflare list size: {}", cf_lists_len);
-
-    let sum_cf_lists_count = cf_lists.as_ref().and_then(|lists| {
-        Some(
-            lists
-                .iter()
-                .filter_map(|list| list["count"].as_u64())
-                .sum::<u64>(),
-        )
+    info!(size = cf_lists_len, "Cloudflare list size");
+    metrics::CF_LISTS.set(cf_lists_len as i64);
+
+    let sum_cf_lists_count = cf_lists.as_ref().map(|lists| {
+        lists
+            .iter()
+            .filter_map(|list| list["count"].as_u64())
+            .sum::<u64>()
     });
 
+    report.sync.deployed_before = sum_cf_lists_count;
+
     let is_need_update =
         sum_cf_lists_count.map_or_else(|| true, |sum| sum != black_list.len() as u64);
     if !is_need_update {
-        println!("No need to update.");
+        info!("No need to update.");
+        report.sync.items_added = Some(0);
+        report.sync.items_removed = Some(0);
+        report.status = RunStatus::Unchanged;
         return Ok(());
     }
 
+    let deployed = async {
+        let timer = metrics::time_phase("fetch_deployed");
+        let deployed = match cf_lists.as_ref() {
+            Some(lists) => utils::get_deployed_domains(lists).await,
+            None => None,
+        };
+        report
+            .durations
+            .insert("fetch_deployed".to_owned(), timer.finish());
+        deployed
+    }
+    .instrument(info_span!("phase", name = "fetch_deployed"))
+    .await;
+    let changes = deployed.as_ref().map(|deployed| {
+        let added = temp_list.difference(deployed).count();
+        let removed = deployed.difference(temp_list).count();
+        info!(added, removed, "Changes against deployed lists");
+        report.sync.items_added = Some(added);
+        report.sync.items_removed = Some(removed);
+        (added, removed)
+    });
+
+    // Nothing has been touched yet, this is the last point to back out
+    check_budget(report, changes, sum_cf_lists_count, cli::CLI.force)?;
+
     let policy_prefix = format!("{cf_prefix} Block Ads");
     let deleted_policy = cloudflare::delete_gateway_policy(&policy_prefix).await;
-    println!("Deleted {deleted_policy} gateway policies");
+    info!(count = deleted_policy, "Deleted gateway policies");
+    report.sync.policies_deleted = deleted_policy;
 
     // Delete all lists parallely tokio
     // let delete_list_tasks = cf_lists.as_ref().and_then(|lists| {
@@ -86,20 +373,27 @@ async fn exec() -> Result<(), Box<dyn Error>> {
     //     join_all(tasks).await;
     // }
 
-    if let Some(lists) = cf_lists.as_ref() {
-        for list in lists.iter() {
-            let name = list["name"].as_str();
-            let id = list["id"].as_str();
-            match (name, id) {
-                (Some(name), Some(id)) => {
-                    println!("Deleting list {name} - ID:{id}");
-                    cloudflare::delete_cf_list(id.as_ref()).await;
+    async {
+        let timer = metrics::time_phase("delete_lists");
+        if let Some(lists) = cf_lists.as_ref() {
+            for list in lists.iter() {
+                let name = list["name"].as_str();
+                let id = list["id"].as_str();
+                if let (Some(name), Some(id)) = (name, id) {
+                    info!(list_name = name, list_id = id, "Deleting list");
+                    if cloudflare::delete_cf_list(id).await.is_some() {
+                        report.sync.lists_deleted += 1;
+                    }
                 }
-                _ => {}
+                tokio::time::sleep(tokio::time::Duration::from_secs(SLEEP_TIME_SEC)).await;
             }
-            tokio::time::sleep(tokio::time::Duration::from_secs(SLEEP_TIME_SEC)).await;
         }
+        report
+            .durations
+            .insert("delete_lists".to_owned(), timer.finish());
     }
+    .instrument(info_span!("phase", name = "delete_lists"))
+    .await;
 
     // tokio::time::sleep(tokio::time::Duration::from_secs(60 * 5)).await;
 
@@ -115,14 +409,23 @@ async fn exec() -> Result<(), Box<dyn Error>> {
     //     .collect::<Vec<_>>();
     // let new_cf_list = join_all(create_list_tasks).await;
 
-    let mut new_cf_list: Vec<Option<serde_json::Value>> = Vec::new();
-    for (i, chunk) in black_list.chunks(1000).enumerate() {
-        let name = format!("{cf_prefix} {i}");
-        let chunk_str_refs = chunk.iter().map(|&s| s).collect::<Vec<_>>();
-        println!("Creating list {name}");
-        new_cf_list.push(cloudflare::create_cf_list(name, chunk_str_refs).await);
-        tokio::time::sleep(tokio::time::Duration::from_secs(SLEEP_TIME_SEC)).await;
+    let new_cf_list = async {
+        let timer = metrics::time_phase("create_lists");
+        let mut new_cf_list: Vec<Option<serde_json::Value>> = Vec::new();
+        for (i, chunk) in black_list.chunks(1000).enumerate() {
+            let name = format!("{cf_prefix} {i}");
+            let chunk_str_refs = chunk.to_vec();
+            info!(list_name = %name, "Creating list");
+            new_cf_list.push(cloudflare::create_cf_list(name, chunk_str_refs).await);
+            tokio::time::sleep(tokio::time::Duration::from_secs(SLEEP_TIME_SEC)).await;
+        }
+        report
+            .durations
+            .insert("create_lists".to_owned(), timer.finish());
+        new_cf_list
     }
+    .instrument(info_span!("phase", name = "create_lists"))
+    .await;
 
     let new_cf_list_ids = new_cf_list
         .iter()
@@ -131,37 +434,91 @@ async fn exec() -> Result<(), Box<dyn Error>> {
 
     let expected_cf_list_count = new_cf_list.len();
     let actual_cf_list_count = new_cf_list_ids.len();
+    metrics::CF_LISTS.set(actual_cf_list_count as i64);
+    report.sync.lists_created = actual_cf_list_count;
+    report.sync.lists_failed = expected_cf_list_count - actual_cf_list_count;
 
-    let cf_policies = match cloudflare::get_gateway_policies(&policy_prefix).await {
-        Some(cf_policies) => cf_policies,
-        None => {
-            println!("No cloudflare policy found");
-            Vec::new()
-        }
-    };
-    if cf_policies.len() == 0 {
-        println!("Creating firewall policy");
-        cloudflare::create_gateway_policy(&policy_prefix, &new_cf_list_ids).await;
-    } else if cf_policies.len() != 1 {
-        println!("More than one firewall policy found");
-    } else {
-        println!("Updating firewall policy");
-        let cf_policy_id = cf_policies.first().and_then(|policy| policy["id"].as_str());
-        match cf_policy_id {
-            Some(cf_policy_id) => {
-                cloudflare::update_gateway_policy(&policy_prefix, &cf_policy_id, &new_cf_list_ids)
-                    .await;
-            }
+    async {
+        let timer = metrics::time_phase("policy");
+        let cf_policies = match cloudflare::get_gateway_policies(&policy_prefix).await {
+            Some(cf_policies) => cf_policies,
             None => {
-                println!("No firewall policy found");
+                info!("No cloudflare policy found");
+                Vec::new()
+            }
+        };
+        if cf_policies.is_empty() {
+            info!("Creating firewall policy");
+            let created = cloudflare::create_gateway_policy(&policy_prefix, &new_cf_list_ids).await;
+            report.sync.policy_action = Some(
+                if created.is_some() {
+                    "created"
+                } else {
+                    "create failed"
+                }
+                .to_owned(),
+            );
+        } else if cf_policies.len() != 1 {
+            warn!(
+                count = cf_policies.len(),
+                "More than one firewall policy found"
+            );
+            report.sync.policy_action = Some("skipped, more than one policy".to_owned());
+        } else {
+            let cf_policy_id = cf_policies.first().and_then(|policy| policy["id"].as_str());
+            match cf_policy_id {
+                Some(cf_policy_id) => {
+                    info!(policy_id = cf_policy_id, "Updating firewall policy");
+                    let updated = cloudflare::update_gateway_policy(
+                        &policy_prefix,
+                        cf_policy_id,
+                        &new_cf_list_ids,
+                    )
+                    .await;
+                    report.sync.policy_action = Some(
+                        if updated.is_some() {
+                            "updated"
+                        } else {
+                            "update failed"
+                        }
+                        .to_owned(),
+                    );
+                }
+                None => {
+                    warn!("No firewall policy found");
+                }
             }
         }
+        report.durations.insert("policy".to_owned(), timer.finish());
     }
+    .instrument(info_span!("phase", name = "policy"))
+    .await;
     if expected_cf_list_count == actual_cf_list_count {
         return Ok(());
     }
-    return Err(format!(
-        "Not all lists are added, {actual_cf_list_count}/{expected_cf_list_count}"
-    )
-    .into());
+    Err(format!("Not all lists are added, {actual_cf_list_count}/{expected_cf_list_count}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_deployed_lists_are_retried() {
+        let report = RunReport::new();
+        let e = check_budget(&report, None, None, false).unwrap_err();
+        assert!(!is_final_error(e.as_ref()));
+        let e = check_budget(&report, None, Some(100), false).unwrap_err();
+        assert!(!is_final_error(e.as_ref()));
+        assert!(check_budget(&report, None, None, true).is_ok());
+    }
+
+    #[test]
+    fn budget_overruns_are_final() {
+        let report = RunReport::new();
+        let e = check_budget(&report, Some((0, 100)), Some(100), false).unwrap_err();
+        assert!(is_final_error(e.as_ref()));
+        assert!(check_budget(&report, Some((0, 100)), Some(100), true).is_ok());
+        assert!(check_budget(&report, Some((1, 1)), Some(100), false).is_ok());
+    }
 }
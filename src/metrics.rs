@@ -0,0 +1,237 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_gauge, register_gauge_vec, register_int_counter_vec, register_int_gauge,
+    register_int_gauge_vec, Encoder, Gauge, GaugeVec, IntCounterVec, IntGauge, IntGaugeVec,
+    TextEncoder,
+};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, error, info, warn};
+
+fn registered<T>(result: prometheus::Result<T>) -> T {
+    match result {
+        Ok(metric) => metric,
+        Err(e) => panic!("Error registering metric: {}", e),
+    }
+}
+
+pub static SOURCE_DOMAINS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    registered(register_int_gauge_vec!(
+        "cgp_source_domains",
+        "Valid domains parsed from each source in the last run",
+        &["list", "source"]
+    ))
+});
+
+pub static SOURCE_FAILED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    registered(register_int_gauge_vec!(
+        "cgp_source_failed",
+        "1 when the source could not be fetched in the last run, whether or not a cached copy was used",
+        &["list", "source"]
+    ))
+});
+
+pub static SOURCE_QUARANTINED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    registered(register_int_gauge_vec!(
+        "cgp_source_quarantined",
+        "1 when the source looked anomalous in the last run and was held back",
+        &["list", "source"]
+    ))
+});
+
+pub static DOMAINS_TOTAL: Lazy<IntGaugeVec> = Lazy::new(|| {
+    registered(register_int_gauge_vec!(
+        "cgp_domains_total",
+        "Unique domains after aggregation in the last run, by kind (block or allow)",
+        &["kind"]
+    ))
+});
+
+pub static DOMAINS_DROPPED: Lazy<IntGaugeVec> = Lazy::new(|| {
+    registered(register_int_gauge_vec!(
+        "cgp_domains_dropped",
+        "Domains removed from the block list in the last run, by reason",
+        &["reason"]
+    ))
+});
+
+pub static PHASE_DURATION: Lazy<GaugeVec> = Lazy::new(|| {
+    registered(register_gauge_vec!(
+        "cgp_phase_duration_seconds",
+        "Duration of each phase of the last run",
+        &["phase"]
+    ))
+});
+
+pub static CF_API_REQUESTS: Lazy<IntCounterVec> = Lazy::new(|| {
+    registered(register_int_counter_vec!(
+        "cgp_cloudflare_api_requests_total",
+        "Cloudflare API calls by operation and HTTP status code",
+        &["operation", "status"]
+    ))
+});
+
+pub static SYNC_RUNS: Lazy<IntCounterVec> = Lazy::new(|| {
+    registered(register_int_counter_vec!(
+        "cgp_sync_runs_total",
+        "Sync attempts by result",
+        &["result"]
+    ))
+});
+
+pub static LAST_SUCCESS: Lazy<Gauge> = Lazy::new(|| {
+    registered(register_gauge!(
+        "cgp_last_success_timestamp_seconds",
+        "Unix time of the last successful sync"
+    ))
+});
+
+pub static CF_LISTS: Lazy<IntGauge> = Lazy::new(|| {
+    registered(register_int_gauge!(
+        "cgp_cloudflare_lists",
+        "Cloudflare Gateway lists managed by this tool"
+    ))
+});
+
+pub static CF_LISTS_CAPACITY: Lazy<IntGauge> = Lazy::new(|| {
+    registered(register_int_gauge!(
+        "cgp_cloudflare_lists_capacity",
+        "Maximum number of Cloudflare Gateway lists the account allows"
+    ))
+});
+
+// Registers every metric up front so they are exported before the first run finishes
+pub fn init(max_lists: u32) {
+    Lazy::force(&SOURCE_DOMAINS);
+    Lazy::force(&DOMAINS_TOTAL);
+    Lazy::force(&DOMAINS_DROPPED);
+    Lazy::force(&PHASE_DURATION);
+    Lazy::force(&CF_API_REQUESTS);
+    Lazy::force(&SYNC_RUNS);
+    Lazy::force(&LAST_SUCCESS);
+    Lazy::force(&CF_LISTS);
+    CF_LISTS_CAPACITY.set(max_lists as i64);
+}
+
+// Records the elapsed time of a phase when finished or dropped
+pub struct PhaseTimer {
+    phase: &'static str,
+    start: Instant,
+    recorded: bool,
+}
+
+pub fn time_phase(phase: &'static str) -> PhaseTimer {
+    PhaseTimer {
+        phase,
+        start: Instant::now(),
+        recorded: false,
+    }
+}
+
+impl PhaseTimer {
+    // Returns the elapsed seconds so the caller can also put them in the run report
+    pub fn finish(mut self) -> f64 {
+        self.record()
+    }
+
+    fn record(&mut self) -> f64 {
+        let seconds = self.start.elapsed().as_secs_f64();
+        PHASE_DURATION.with_label_values(&[self.phase]).set(seconds);
+        self.recorded = true;
+        seconds
+    }
+}
+
+impl Drop for PhaseTimer {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.record();
+        }
+    }
+}
+
+pub fn record_cf_api(operation: &str, status: Option<reqwest::StatusCode>) {
+    let status = status.map_or_else(|| "error".to_owned(), |s| s.as_u16().to_string());
+    CF_API_REQUESTS
+        .with_label_values(&[operation, status.as_str()])
+        .inc();
+}
+
+pub async fn serve(addr: SocketAddr) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(%addr, error = %e, "Error binding metrics listener");
+            return;
+        }
+    };
+    info!(%addr, "Serving metrics on /metrics");
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(handle(stream));
+            }
+            Err(e) => warn!(error = %e, "Error accepting metrics connection"),
+        }
+    }
+}
+
+// A scrape request is a few hundred bytes, anything slower or larger is dropped unanswered
+static MAX_REQUEST_HEAD: u64 = 8 * 1024;
+static REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The request line once the headers are read, None for a request cut short or over the limit
+async fn read_request(reader: &mut (impl AsyncBufReadExt + Unpin)) -> Option<String> {
+    let mut request_line = String::new();
+    match reader.read_line(&mut request_line).await {
+        Ok(_) if request_line.ends_with('\n') => {}
+        _ => return None,
+    }
+    // Drain the headers, the request body is never used
+    let mut header = String::new();
+    loop {
+        match reader.read_line(&mut header).await {
+            Ok(_) if header == "\r\n" || header == "\n" => return Some(request_line),
+            Ok(_) if header.ends_with('\n') => header.clear(),
+            _ => return None,
+        }
+    }
+}
+
+async fn handle(stream: TcpStream) {
+    let mut reader = BufReader::new(stream.take(MAX_REQUEST_HEAD));
+    let request_line = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut reader)).await
+    {
+        Ok(Some(request_line)) => request_line,
+        Ok(None) => {
+            debug!("Dropped a metrics request cut short or over the size limit");
+            return;
+        }
+        Err(_) => {
+            debug!("Dropped a metrics request too slow to arrive");
+            return;
+        }
+    };
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match path {
+        "/metrics" => {
+            let mut buffer = Vec::new();
+            match TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+                Ok(_) => ("200 OK", buffer),
+                Err(e) => ("500 Internal Server Error", e.to_string().into_bytes()),
+            }
+        }
+        _ => ("404 Not Found", b"Not Found".to_vec()),
+    };
+    let head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    let mut stream = reader.into_inner().into_inner();
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(&body).await;
+    let _ = stream.shutdown().await;
+}
@@ -0,0 +1,199 @@
+use once_cell::sync::Lazy;
+use reqwest::{Client, Url};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{error, info, instrument, warn};
+
+use crate::report::{RunReport, RunStatus};
+
+static MAX_ATTEMPTS: u32 = 4;
+
+pub static DEFAULT_TEMPLATE: &str = "Cloudflare Gateway sync: {status}
+Block list: {block} domains (+{added} / -{removed}), allow list: {allow} domains
+Lists: {lists_created} created, {lists_deleted} deleted, {lists_failed} failed
+Policy: {policy_action}
+{error}";
+
+static CLIENT: Lazy<Client> =
+    Lazy::new(
+        || match Client::builder().timeout(Duration::from_secs(30)).build() {
+            Ok(client) => client,
+            Err(e) => panic!("Error creating client: {}", e),
+        },
+    );
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SinkKind {
+    Webhook,
+    Slack,
+    Discord,
+    Telegram,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Failure,
+    Changes,
+    Always,
+}
+
+// Parsed from `kind[@when[,when]]=url`, e.g. `slack@failure,changes=https://hooks.slack.com/...`
+#[derive(Clone, Debug)]
+pub struct Sink {
+    pub kind: SinkKind,
+    pub triggers: Vec<Trigger>,
+    pub url: Url,
+}
+
+impl FromStr for Sink {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let (head, url) = spec
+            .split_once('=')
+            .ok_or_else(|| format!("Expected kind[@when]=url, got \"{spec}\""))?;
+        let (kind, triggers) = head.split_once('@').unwrap_or((head, "failure"));
+        let kind = match kind.trim().to_lowercase().as_str() {
+            "webhook" | "json" => SinkKind::Webhook,
+            "slack" => SinkKind::Slack,
+            "discord" => SinkKind::Discord,
+            "telegram" => SinkKind::Telegram,
+            other => return Err(format!("Unknown notification kind \"{other}\"")),
+        };
+        let triggers = triggers
+            .split(',')
+            .map(|trigger| match trigger.trim().to_lowercase().as_str() {
+                "failure" => Ok(Trigger::Failure),
+                "changes" => Ok(Trigger::Changes),
+                "always" => Ok(Trigger::Always),
+                other => Err(format!("Unknown notification trigger \"{other}\"")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let url = Url::parse(url.trim()).map_err(|e| format!("Invalid URL \"{url}\": {e}"))?;
+        Ok(Sink {
+            kind,
+            triggers,
+            url,
+        })
+    }
+}
+
+impl Sink {
+    fn should_fire(&self, report: &RunReport) -> bool {
+        self.triggers.iter().any(|trigger| match trigger {
+            Trigger::Always => true,
+            Trigger::Failure => report.status == RunStatus::Failure,
+            Trigger::Changes => report.status == RunStatus::Success && has_changes(report),
+        })
+    }
+
+    fn payload(&self, report: &RunReport, text: &str) -> (Url, serde_json::Value) {
+        match self.kind {
+            SinkKind::Webhook => (
+                self.url.clone(),
+                serde_json::json!({
+                    "message": text,
+                    "status": report.status,
+                    "report": report,
+                }),
+            ),
+            SinkKind::Slack => (self.url.clone(), serde_json::json!({ "text": text })),
+            // Discord rejects messages longer than 2000 characters
+            SinkKind::Discord => (
+                self.url.clone(),
+                serde_json::json!({ "content": text.chars().take(2000).collect::<String>() }),
+            ),
+            // The chat is taken from the URL query, e.g. https://api.telegram.org/bot<token>/sendMessage?chat_id=<id>
+            SinkKind::Telegram => {
+                let chat_id = self
+                    .url
+                    .query_pairs()
+                    .find(|(key, _)| key == "chat_id")
+                    .map(|(_, value)| value.into_owned())
+                    .unwrap_or_default();
+                let mut url = self.url.clone();
+                url.set_query(None);
+                (url, serde_json::json!({ "chat_id": chat_id, "text": text }))
+            }
+        }
+    }
+}
+
+fn has_changes(report: &RunReport) -> bool {
+    // Unknown diffs count as changes, better one message too many than a silent swing
+    report.sync.items_added.is_none_or(|added| added > 0)
+        || report.sync.items_removed.is_none_or(|removed| removed > 0)
+}
+
+pub fn render(template: &str, report: &RunReport) -> String {
+    let optional = |value: Option<usize>| value.map_or_else(|| "?".to_owned(), |v| v.to_string());
+    let error = report
+        .error
+        .as_ref()
+        .map(|error| format!("Error: {error}"))
+        .unwrap_or_default();
+    let duration = report
+        .finished_at
+        .map(|finished_at| (finished_at - report.started_at).num_seconds())
+        .unwrap_or_default();
+    let replacements = [
+        ("{status}", report.status.as_str().to_owned()),
+        ("{started_at}", report.started_at.to_rfc3339()),
+        ("{duration}", format!("{duration}s")),
+        ("{allow}", report.totals.allow.to_string()),
+        ("{block}", report.totals.block.to_string()),
+        ("{added}", optional(report.sync.items_added)),
+        ("{removed}", optional(report.sync.items_removed)),
+        ("{lists_created}", report.sync.lists_created.to_string()),
+        ("{lists_deleted}", report.sync.lists_deleted.to_string()),
+        ("{lists_failed}", report.sync.lists_failed.to_string()),
+        (
+            "{policy_action}",
+            report
+                .sync
+                .policy_action
+                .clone()
+                .unwrap_or_else(|| "none".to_owned()),
+        ),
+        ("{error}", error),
+    ];
+    let mut message = template.replace("\\n", "\n");
+    for (placeholder, value) in replacements {
+        message = message.replace(placeholder, &value);
+    }
+    message.trim_end().to_owned()
+}
+
+pub async fn notify(sinks: &[Sink], template: &str, report: &RunReport) {
+    let text = render(template, report);
+    for sink in sinks.iter().filter(|sink| sink.should_fire(report)) {
+        let (url, payload) = sink.payload(report, &text);
+        deliver(sink.kind, url, &payload).await;
+    }
+}
+
+#[instrument(skip_all, fields(kind = ?kind, host = url.host_str().unwrap_or_default()))]
+async fn deliver(kind: SinkKind, url: Url, payload: &serde_json::Value) {
+    for attempt in 1..=MAX_ATTEMPTS {
+        match CLIENT.post(url.clone()).json(payload).send().await {
+            Ok(resp) if resp.status().is_success() => {
+                info!(status = resp.status().as_u16(), "Sent notification");
+                return;
+            }
+            Ok(resp) => {
+                let status = resp.status();
+                // Only rate limiting and server errors are worth another try
+                if !(status.is_server_error() || status == 429) {
+                    error!(status = status.as_u16(), "Notification rejected");
+                    return;
+                }
+                warn!(status = status.as_u16(), attempt, "Notification failed");
+            }
+            Err(e) => warn!(error = %e, attempt, "Error sending notification"),
+        }
+        if attempt < MAX_ATTEMPTS {
+            tokio::time::sleep(Duration::from_secs(2u64.pow(attempt - 1))).await;
+        }
+    }
+    error!(attempts = MAX_ATTEMPTS, "Giving up on notification");
+}
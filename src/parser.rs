@@ -0,0 +1,507 @@
+use clap::ValueEnum;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::net::IpAddr;
+use std::str::FromStr;
+use tracing::warn;
+
+use crate::cli;
+use crate::report::{Outcome, Outcomes};
+
+mod adblock;
+mod csv;
+mod dnsmasq;
+mod domains;
+mod hosts;
+mod json;
+mod misp;
+mod name;
+mod rpz;
+mod squid;
+mod stix;
+mod unbound;
+mod urls;
+
+pub use json::JsonPath;
+
+// Lines looked at to guess the format, enough to get past the header of any list
+static DETECT_SAMPLE_LINES: usize = 500;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `0.0.0.0 example.com`, other addresses are redirects
+    Hosts,
+    /// One domain per line
+    Domains,
+    /// `*.example.com`, the name and everything below it
+    Wildcard,
+    /// AdBlock / AdGuard DNS rules, `||example.com^`
+    Adblock,
+    /// `address=/example.com/` and `server=/example.com/`
+    Dnsmasq,
+    /// `local-zone: "example.com." always_nxdomain` and `local-data`
+    Unbound,
+    /// Squid `dstdomain` files and Privoxy action files, `.example.com`
+    #[value(alias = "privoxy")]
+    Squid,
+    /// Response Policy Zones, `example.com CNAME .`
+    Rpz,
+    /// Phishing and malware URL feeds, `http://example.com/path`
+    Url,
+    /// Comma separated values, the column is picked with `column=`
+    Csv,
+    /// JSON documents, the names are picked with `path=`
+    Json,
+    /// STIX 2.1 bundles, `domain-name` observables and indicators
+    Stix,
+    /// MISP event exports, `domain` and `hostname` attributes
+    Misp,
+}
+
+// What to do with a URL on a host many unrelated users share, e.g. docs.google.com
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SharedHosts {
+    /// Leave the host out and count the URL as skipped
+    Skip,
+    /// Block the host like any other
+    Block,
+}
+
+// Whether names with an underscore, e.g. `_dmarc.example.com`, are kept
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Underscores {
+    /// Keep them in any label but the top level one
+    Allow,
+    /// Count them as invalid, for DNS servers that only take hostnames
+    Reject,
+}
+
+impl Format {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Format::Hosts => "hosts",
+            Format::Domains => "domains",
+            Format::Wildcard => "wildcard",
+            Format::Adblock => "adblock",
+            Format::Dnsmasq => "dnsmasq",
+            Format::Unbound => "unbound",
+            Format::Squid => "squid",
+            Format::Rpz => "rpz",
+            Format::Url => "url",
+            Format::Csv => "csv",
+            Format::Json => "json",
+            Format::Stix => "stix",
+            Format::Misp => "misp",
+        }
+    }
+
+    // Whether a line only makes sense in this format, plain domain lists have no such lines
+    fn is_signature(&self, line: &str) -> bool {
+        match self {
+            Format::Hosts => hosts::is_signature(line),
+            // CSV is told apart by its source options, JSON documents by parsing the whole body
+            Format::Domains | Format::Csv | Format::Json | Format::Stix | Format::Misp => false,
+            Format::Wildcard => domains::is_wildcard_signature(line),
+            Format::Adblock => adblock::is_signature(line),
+            Format::Dnsmasq => dnsmasq::is_signature(line),
+            Format::Unbound => unbound::is_signature(line),
+            Format::Squid => squid::is_signature(line),
+            Format::Rpz => rpz::is_signature(line),
+            Format::Url => urls::is_signature(line),
+        }
+    }
+}
+
+// The format with the most signature lines in the start of the body, domains when there are none
+pub fn detect(body: &str) -> Format {
+    if body.trim_start().starts_with(['{', '[']) {
+        if let Ok(root) = serde_json::from_str::<Value>(body) {
+            if stix::is_bundle(&root) {
+                return Format::Stix;
+            }
+            if misp::is_export(&root) {
+                return Format::Misp;
+            }
+            return Format::Json;
+        }
+    }
+    let candidates = [
+        Format::Hosts,
+        Format::Adblock,
+        Format::Dnsmasq,
+        Format::Unbound,
+        Format::Squid,
+        Format::Rpz,
+        Format::Url,
+        Format::Wildcard,
+    ];
+    let mut scores = [0usize; 8];
+    for line in body
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .take(DETECT_SAMPLE_LINES)
+    {
+        for (score, format) in scores.iter_mut().zip(candidates) {
+            if format.is_signature(line) {
+                *score += 1;
+            }
+        }
+    }
+    // max_by_key keeps the last of equal scores, going backwards lets the earlier candidate win ties
+    candidates
+        .into_iter()
+        .zip(scores)
+        .rev()
+        .filter(|(_, score)| *score > 0)
+        .max_by_key(|(_, score)| *score)
+        .map_or(Format::Domains, |(format, _)| format)
+}
+
+// Fields that hold the name in CSV headers and JSON objects when no column or path is given
+static NAME_FIELDS: [&str; 5] = ["domain", "hostname", "host", "fqdn", "indicator"];
+
+// A CSV column, by header name or by position counted from 1
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Name(String),
+    Index(usize),
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value.parse::<usize>() {
+            Ok(0) => Err("Columns are counted from 1".to_owned()),
+            Ok(index) => Ok(Column::Index(index - 1)),
+            Err(_) if value.is_empty() => Err("Empty column".to_owned()),
+            Err(_) => Ok(Column::Name(value.to_lowercase())),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Eq,
+    Ne,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+}
+
+// `confidence>=80`, compared as numbers when both sides are numbers and as text otherwise
+#[derive(Clone, Debug)]
+pub struct Condition {
+    field: String,
+    operator: Operator,
+    value: String,
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(condition: &str) -> Result<Self, String> {
+        let Some(start) = condition.find(['<', '>', '=', '!']) else {
+            return Err(format!("No operator in {condition}"));
+        };
+        let (field, rest) = condition.split_at(start);
+        let operators = [
+            (">=", Operator::Ge),
+            ("<=", Operator::Le),
+            ("!=", Operator::Ne),
+            ("==", Operator::Eq),
+            ("=", Operator::Eq),
+            (">", Operator::Gt),
+            ("<", Operator::Lt),
+        ];
+        let Some((operator, value)) = operators
+            .into_iter()
+            .find_map(|(symbol, operator)| Some((operator, rest.strip_prefix(symbol)?)))
+        else {
+            return Err(format!("No operator in {condition}"));
+        };
+        if field.is_empty() {
+            return Err(format!("No field in {condition}"));
+        }
+        // CSV headers are matched in lowercase, JSON keys as written
+        Ok(Condition {
+            field: field.to_owned(),
+            operator,
+            value: value.to_owned(),
+        })
+    }
+}
+
+impl Condition {
+    fn holds(&self, actual: &str) -> bool {
+        let actual = actual.trim();
+        let ordering = match (actual.parse::<f64>(), self.value.parse::<f64>()) {
+            (Ok(actual), Ok(expected)) => actual.partial_cmp(&expected),
+            _ => Some(actual.to_lowercase().cmp(&self.value.to_lowercase())),
+        };
+        let Some(ordering) = ordering else {
+            return false;
+        };
+        match self.operator {
+            Operator::Eq => ordering == Ordering::Equal,
+            Operator::Ne => ordering != Ordering::Equal,
+            Operator::Ge => ordering != Ordering::Less,
+            Operator::Le => ordering != Ordering::Greater,
+            Operator::Gt => ordering == Ordering::Greater,
+            Operator::Lt => ordering == Ordering::Less,
+        }
+    }
+}
+
+// Where CSV and JSON sources keep their names, set with source options
+#[derive(Clone, Debug)]
+pub struct Selector {
+    pub delimiter: char,
+    // None when fields are never quoted
+    pub quote: Option<char>,
+    // Whether the first row names the columns, by default unless the column is given by position
+    pub header: Option<bool>,
+    pub column: Option<Column>,
+    pub path: Option<JsonPath>,
+    // Rows and objects are only taken when every condition holds
+    pub conditions: Vec<Condition>,
+    // Threshold of STIX and MISP sources instead of --min-confidence
+    pub min_confidence: Option<u8>,
+}
+
+impl Default for Selector {
+    fn default() -> Self {
+        Selector {
+            delimiter: ',',
+            quote: Some('"'),
+            header: None,
+            column: None,
+            path: None,
+            conditions: Vec::new(),
+            min_confidence: None,
+        }
+    }
+}
+
+// An allow rule found in a block list source
+pub struct Exception {
+    pub name: String,
+    // Whether the rule covers the subdomains of the name too
+    pub subdomains: bool,
+    // $important exceptions win over $important blocks
+    pub important: bool,
+}
+
+#[derive(Default)]
+pub struct Parsed {
+    pub block: Vec<String>,
+    // Blocked names that exceptions don't apply to
+    pub important: Vec<String>,
+    pub allow: Vec<Exception>,
+    // Names a $denyallow rule leaves out, they only join the allow list
+    pub carve_outs: Vec<String>,
+    // Names a hosts file points at an address other than a sinkhole
+    pub overrides: Vec<(String, IpAddr)>,
+    // Rules that can't be expressed in a DNS block list, e.g. client specific ones
+    pub skipped: usize,
+    // Blank, comment and invalid lines and the names that didn't normalize, with samples
+    pub outcomes: Outcomes,
+}
+
+// Valid names found in the body
+pub fn parse(body: &str, format: Format, selector: &Selector) -> Parsed {
+    let min_confidence = selector.min_confidence.unwrap_or(cli::CLI.min_confidence);
+    let rules = match format {
+        Format::Hosts => hosts::parse(body),
+        Format::Domains => domains::parse(body, false),
+        Format::Wildcard => domains::parse(body, true),
+        Format::Adblock => adblock::parse(body),
+        Format::Dnsmasq => dnsmasq::parse(body),
+        Format::Unbound => unbound::parse(body),
+        Format::Squid => squid::parse(body),
+        Format::Rpz => rpz::parse(body),
+        Format::Url => urls::parse(body),
+        Format::Csv => csv::parse(body, selector),
+        Format::Json => json::parse(body, selector),
+        Format::Stix => document(body, |root| stix::parse(root, min_confidence)),
+        Format::Misp => document(body, |root| misp::parse(root, min_confidence)),
+    };
+    let mut outcomes = Outcomes::default();
+    for line in body.lines().map(str::trim) {
+        if line.is_empty() {
+            outcomes.count(Outcome::Blank, 1);
+        } else if is_comment(format, line) {
+            outcomes.record(Outcome::Comment, line);
+        }
+    }
+    for line in &rules.invalid {
+        outcomes.record(Outcome::InvalidSyntax, line);
+    }
+    let mut normalize_all = |names: Vec<Cow<str>>| {
+        names
+            .iter()
+            .filter_map(|name| checked(name, &mut outcomes))
+            .collect::<Vec<_>>()
+    };
+    let block = normalize_all(rules.block);
+    let important = normalize_all(rules.important);
+    let carve_outs = normalize_all(rules.carve_outs);
+    Parsed {
+        block,
+        important,
+        allow: rules
+            .allow
+            .into_iter()
+            .filter_map(|(name, subdomains, important)| {
+                Some(Exception {
+                    name: checked(&name, &mut outcomes)?,
+                    subdomains,
+                    important,
+                })
+            })
+            .collect(),
+        carve_outs,
+        overrides: rules
+            .overrides
+            .into_iter()
+            .filter_map(|(name, ip)| Some((checked(&name, &mut outcomes)?, ip)))
+            .collect(),
+        skipped: rules.skipped,
+        outcomes,
+    }
+}
+
+// Whole line comments of each format, JSON documents have none
+fn is_comment(format: Format, line: &str) -> bool {
+    match format {
+        Format::Adblock => line.starts_with(['!', '[']) || adblock::is_comment(line),
+        Format::Rpz => line.starts_with(';'),
+        Format::Json | Format::Stix | Format::Misp => false,
+        _ => line.starts_with('#'),
+    }
+}
+
+// The normalized name, or the reason it was dropped counted with the name as it came
+fn checked(name: &str, outcomes: &mut Outcomes) -> Option<String> {
+    match normalize(name) {
+        Ok(name) => Some(name),
+        Err(outcome) => {
+            outcomes.record(outcome, name);
+            None
+        }
+    }
+}
+
+// What a format parser cuts out of the body, before normalization
+#[derive(Default)]
+struct Rules<'a> {
+    block: Vec<Cow<'a, str>>,
+    important: Vec<Cow<'a, str>>,
+    // Exception names, whether they cover subdomains and whether they are $important
+    allow: Vec<(Cow<'a, str>, bool, bool)>,
+    carve_outs: Vec<Cow<'a, str>>,
+    overrides: Vec<(Cow<'a, str>, IpAddr)>,
+    skipped: usize,
+    // Lines the format parser couldn't read
+    invalid: Vec<Cow<'a, str>>,
+}
+
+// Threat intel formats are read as a whole document
+fn document(body: &str, parse: impl Fn(&Value) -> Rules<'static>) -> Rules<'static> {
+    match serde_json::from_str::<Value>(body) {
+        Ok(root) => parse(&root),
+        Err(e) => {
+            warn!(error = %e, "Error parsing JSON source");
+            Rules::default()
+        }
+    }
+}
+
+// Everything before an inline comment, trimmed
+fn strip_comment(line: &str, marker: char) -> &str {
+    line.split(marker).next().unwrap_or_default().trim()
+}
+
+static IP_PATTERN: Lazy<Regex> =
+    Lazy::new(
+        || match Regex::new(r"^\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}$") {
+            Ok(re) => re,
+            Err(e) => panic!("Error compiling regex: {}", e),
+        },
+    );
+
+// Shared by every format once the name is cut out of its line
+fn normalize(name: &str) -> Result<String, Outcome> {
+    let name = name
+        .trim()
+        .to_lowercase()
+        .trim_start_matches("*.")
+        .trim_start_matches('.')
+        .to_owned();
+    // A fully qualified name ends with a single dot
+    let name = name.strip_suffix('.').unwrap_or(&name);
+    let address = name.trim_start_matches('[').trim_end_matches(']');
+    if address.parse::<IpAddr>().is_ok() || IP_PATTERN.is_match(name) {
+        return Err(Outcome::IpLiteral);
+    }
+    let name = idna::domain_to_ascii(name).map_err(|_| Outcome::Idna)?;
+    name::validate(&name, cli::CLI.underscores)?;
+    // `www.example.com` is blocked with `example.com`, not `www.com` with `com`
+    match name.strip_prefix("www.") {
+        Some(rest) if rest.contains('.') => Ok(rest.to_owned()),
+        _ => Ok(name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_the_format_with_most_signature_lines() {
+        assert_eq!(
+            detect("0.0.0.0 a.example.com\n127.0.0.1 b.example.com\n"),
+            Format::Hosts
+        );
+        assert_eq!(
+            detect("! Title\n||a.example.com^\n@@||b.example.com^\n"),
+            Format::Adblock
+        );
+        assert_eq!(detect("address=/a.example.com/\n"), Format::Dnsmasq);
+        assert_eq!(
+            detect("server:\nlocal-zone: \"a.example.com.\" always_nxdomain\n"),
+            Format::Unbound
+        );
+        assert_eq!(
+            detect("$ORIGIN rpz.example.net.\na.example.com CNAME .\n"),
+            Format::Rpz
+        );
+        assert_eq!(detect("*.a.example.com\n"), Format::Wildcard);
+        assert_eq!(detect("a.example.com\nb.example.com\n"), Format::Domains);
+    }
+
+    #[test]
+    fn detects_json_documents() {
+        assert_eq!(detect(r#"{"type": "bundle", "objects": []}"#), Format::Stix);
+        assert_eq!(detect(r#"[{"domain": "a.example.com"}]"#), Format::Json);
+    }
+
+    #[test]
+    fn parse_normalizes_and_counts_what_it_drops() {
+        let parsed = parse(
+            "# comment\n\nA.Example.com.\nwww.b.example.com\nlocalhost\n10.0.0.1\n",
+            Format::Domains,
+            &Selector::default(),
+        );
+        assert_eq!(parsed.block, ["a.example.com", "b.example.com"]);
+        assert_eq!(
+            parsed.outcomes.summary(),
+            "blank 1, comment 1, single_label 1, ip_literal 1"
+        );
+    }
+}
@@ -0,0 +1,215 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use super::Rules;
+
+pub(super) fn is_signature(line: &str) -> bool {
+    line.starts_with("||") || line.starts_with("@@") || line.starts_with("[Adblock")
+}
+
+// Element hiding and scriptlet rules, they start with `#` too when they apply to every site
+static COSMETIC_MARKERS: [&str; 5] = ["##", "#@#", "#?#", "#$#", "#%#"];
+
+fn is_cosmetic(line: &str) -> bool {
+    COSMETIC_MARKERS.iter().any(|marker| line.contains(marker))
+}
+
+// AdGuard lists use `#` comments next to `!` ones
+pub(super) fn is_comment(line: &str) -> bool {
+    line.starts_with('#') && !is_cosmetic(line)
+}
+
+// Response codes of $dnsrewrite that keep the name from resolving
+static BLOCKING_REWRITES: [&str; 3] = ["nxdomain", "refused", "servfail"];
+
+struct Rule<'a> {
+    name: &'a str,
+    is_exception: bool,
+    subdomains: bool,
+    important: bool,
+    carve_outs: Vec<&'a str>,
+    // The rule as a $badfilter rule would name it, modifiers sorted
+    key: String,
+}
+
+enum Parsed<'a> {
+    Rule(Rule<'a>),
+    BadFilter(String),
+    // A network rule that can't be expressed in a DNS block list
+    Skipped,
+}
+
+// `||example.com^` covers the name and its subdomains, `|example.com^` and a bare name only the name.
+// `@@` turns the rule into an exception. Cosmetic and URL rules have no meaning for a DNS list
+fn parse_rule(line: &str) -> Option<Parsed<'_>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with(['!', '[']) || is_comment(line) {
+        return None;
+    }
+    // Regular expressions match URLs, not names
+    if is_cosmetic(line) || line.starts_with('/') {
+        return Some(Parsed::Skipped);
+    }
+    let (rule, is_exception) = match line.strip_prefix("@@") {
+        Some(rule) => (rule, true),
+        None => (line, false),
+    };
+    let (pattern, modifiers) = rule.split_once('$').unwrap_or((rule, ""));
+    let (name, subdomains) = match pattern.strip_prefix("||") {
+        Some(name) => (name, true),
+        None => (pattern.strip_prefix('|').unwrap_or(pattern), false),
+    };
+    let name = name.strip_suffix('|').unwrap_or(name);
+    let name = name.strip_suffix('^').unwrap_or(name);
+    if name.is_empty() || name.contains(['/', '^', '|', '*', ':']) {
+        return Some(Parsed::Skipped);
+    }
+
+    let mut modifiers = modifiers
+        .split(',')
+        .map(str::trim)
+        .filter(|modifier| !modifier.is_empty())
+        .collect::<Vec<_>>();
+    modifiers.sort_unstable();
+    let is_badfilter = modifiers.contains(&"badfilter");
+    modifiers.retain(|modifier| *modifier != "badfilter");
+    let key = format!(
+        "{}{}${}",
+        if is_exception { "@@" } else { "" },
+        pattern,
+        modifiers.join(",")
+    );
+    if is_badfilter {
+        return Some(Parsed::BadFilter(key));
+    }
+
+    let mut rule = Rule {
+        name,
+        is_exception,
+        subdomains,
+        important: false,
+        carve_outs: Vec::new(),
+        key,
+    };
+    for modifier in modifiers {
+        let (modifier, value) = modifier.split_once('=').unwrap_or((modifier, ""));
+        match modifier {
+            "important" => rule.important = true,
+            "denyallow" if !is_exception => {
+                rule.carve_outs = value.split('|').filter(|v| !v.is_empty()).collect()
+            }
+            "dnsrewrite" if BLOCKING_REWRITES.contains(&value.to_lowercase().as_str()) => {}
+            // $client and $ctag rules only apply to some devices, which a shared list can't express.
+            // Like AdGuard Home, a rule with any other modifier it can't honour doesn't apply at all
+            _ => return Some(Parsed::Skipped),
+        }
+    }
+    Some(Parsed::Rule(rule))
+}
+
+pub(super) fn parse(body: &str) -> Rules<'_> {
+    let mut parsed = Vec::new();
+    let mut bad_filters = HashSet::new();
+    let mut rules = Rules::default();
+    for rule in body.lines().filter_map(parse_rule) {
+        match rule {
+            Parsed::Rule(rule) => parsed.push(rule),
+            Parsed::BadFilter(key) => {
+                bad_filters.insert(key);
+            }
+            Parsed::Skipped => rules.skipped += 1,
+        }
+    }
+    for rule in parsed {
+        // $badfilter cancels the rule that is the same apart from it
+        if bad_filters.contains(&rule.key) {
+            continue;
+        }
+        if rule.is_exception {
+            rules
+                .allow
+                .push((rule.name.into(), rule.subdomains, rule.important));
+            continue;
+        }
+        rules.block.push(rule.name.into());
+        if rule.important {
+            rules.important.push(rule.name.into());
+        }
+        rules
+            .carve_outs
+            .extend(rule.carve_outs.into_iter().map(Cow::Borrowed));
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exceptions_and_anchors() {
+        let rules = parse(
+            "||ads.example.com^\n|exact.example.com^\n@@||ok.example.com^\n@@|one.example.com^\n",
+        );
+        assert_eq!(rules.block, ["ads.example.com", "exact.example.com"]);
+        let allow = rules
+            .allow
+            .iter()
+            .map(|(name, subdomains, important)| (name.as_ref(), *subdomains, *important))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            allow,
+            [
+                ("ok.example.com", true, false),
+                ("one.example.com", false, false)
+            ]
+        );
+    }
+
+    #[test]
+    fn badfilter_cancels_the_same_rule_whatever_the_modifier_order() {
+        let rules = parse(
+            "||a.example.com^$important,dnsrewrite=NXDOMAIN\n\
+             ||a.example.com^$dnsrewrite=NXDOMAIN,important,badfilter\n\
+             ||b.example.com^\n\
+             ||b.example.com^$important,badfilter\n\
+             @@||c.example.com^\n\
+             ||c.example.com^$badfilter\n",
+        );
+        // A $badfilter only matches a rule with the same modifiers and the same exception marker
+        assert_eq!(rules.block, ["b.example.com"]);
+        assert_eq!(rules.allow.len(), 1);
+    }
+
+    #[test]
+    fn important_blocks_are_marked() {
+        let rules =
+            parse("||a.example.com^$important\n@@||a.example.com^$important\n||b.example.com^\n");
+        assert_eq!(rules.block, ["a.example.com", "b.example.com"]);
+        assert_eq!(rules.important, ["a.example.com"]);
+        assert!(rules.allow[0].2);
+    }
+
+    #[test]
+    fn denyallow_blocks_and_carves_out() {
+        let rules = parse("||example.com^$denyallow=a.example.com|b.example.com\n");
+        assert_eq!(rules.block, ["example.com"]);
+        assert_eq!(rules.carve_outs, ["a.example.com", "b.example.com"]);
+    }
+
+    #[test]
+    fn rules_a_dns_list_cant_express_are_skipped() {
+        let rules = parse(
+            "||a.example.com^$client=192.168.0.1\n\
+             ||b.example.com^$dnsrewrite=1.2.3.4\n\
+             ||c.example.com^$dnsrewrite=REFUSED\n\
+             example.com##.banner\n\
+             /ads[0-9]+/\n\
+             ||d.example.com/path\n\
+             # comment\n\
+             ! comment\n",
+        );
+        assert_eq!(rules.block, ["c.example.com"]);
+        assert_eq!(rules.skipped, 5);
+    }
+}
@@ -0,0 +1,156 @@
+use std::borrow::Cow;
+use tracing::warn;
+
+use super::{Column, Rules, Selector, NAME_FIELDS};
+
+// Fields of one row, a quoted field may hold the delimiter and doubled quotes
+fn split_row(line: &str, delimiter: char, quote: Option<char>) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if quoted {
+            if Some(c) != quote {
+                field.push(c);
+            } else if chars.peek() == Some(&c) {
+                field.push(c);
+                chars.next();
+            } else {
+                quoted = false;
+            }
+        } else if c == delimiter {
+            fields.push(field.trim().to_owned());
+            field.clear();
+        } else if Some(c) == quote && field.trim().is_empty() {
+            quoted = true;
+            field.clear();
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field.trim().to_owned());
+    fields
+}
+
+// Rows are single lines, `#` lines are comments as in the abuse.ch feeds
+pub(super) fn parse<'a>(body: &'a str, selector: &Selector) -> Rules<'a> {
+    let mut rules = Rules::default();
+    let mut rows = body
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| split_row(line, selector.delimiter, selector.quote));
+    let has_header = selector
+        .header
+        .unwrap_or(!matches!(selector.column, Some(Column::Index(_))));
+    let header = if has_header {
+        rows.next()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|field| field.to_lowercase())
+            .collect()
+    } else {
+        Vec::new()
+    };
+    let position = |column: &Column| match column {
+        Column::Index(index) => Some(*index),
+        Column::Name(name) => header.iter().position(|field| field == name),
+    };
+    let column = match &selector.column {
+        Some(column) => position(column),
+        None => Some(
+            NAME_FIELDS
+                .iter()
+                .find_map(|name| header.iter().position(|field| field == name))
+                .unwrap_or(0),
+        ),
+    };
+    let Some(column) = column else {
+        warn!(column = ?selector.column, "Column not found in the CSV header");
+        return rules;
+    };
+    let mut conditions = Vec::new();
+    for condition in &selector.conditions {
+        match condition
+            .field
+            .parse()
+            .ok()
+            .and_then(|field| position(&field))
+        {
+            Some(field) => conditions.push((field, condition)),
+            None => {
+                warn!(
+                    field = condition.field,
+                    "Condition column not found in the CSV header"
+                );
+                return rules;
+            }
+        }
+    }
+
+    for row in rows {
+        let holds = conditions
+            .iter()
+            .all(|(field, condition)| row.get(*field).is_some_and(|value| condition.holds(value)));
+        if !holds {
+            continue;
+        }
+        match row.get(column) {
+            Some(name) if !name.is_empty() => rules.block.push(Cow::Owned(name.to_owned())),
+            Some(_) => rules.skipped += 1,
+            // A row too short for the column
+            None => rules
+                .invalid
+                .push(Cow::Owned(row.join(&selector.delimiter.to_string()))),
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_fields_hold_delimiters_and_doubled_quotes() {
+        assert_eq!(
+            split_row(r#"1, "a,b" ,"say ""hi""",plain"#, ',', Some('"')),
+            ["1", "a,b", r#"say "hi""#, "plain"]
+        );
+        assert_eq!(split_row(r#""a,b",c"#, ',', None), [r#""a"#, r#"b""#, "c"]);
+    }
+
+    #[test]
+    fn the_column_comes_from_the_header_or_the_position() {
+        let body = "# feed\nid,\"Domain\",score\n1,\"a.example.com\",90\n2,b.example.com,10\n3\n";
+        let rules = parse(body, &Selector::default());
+        assert_eq!(rules.block, ["a.example.com", "b.example.com"]);
+        assert_eq!(rules.invalid, ["3"]);
+
+        let selector = Selector {
+            column: Some("3".parse().unwrap()),
+            conditions: vec!["1>=2".parse().unwrap()],
+            ..Selector::default()
+        };
+        let rules = parse(
+            "1,a.example.com,x.example.com\n2,b.example.com,y.example.com\n",
+            &selector,
+        );
+        assert_eq!(rules.block, ["y.example.com"]);
+    }
+
+    #[test]
+    fn conditions_pick_rows() {
+        let selector = Selector {
+            delimiter: ';',
+            conditions: vec!["score>=50".parse().unwrap()],
+            ..Selector::default()
+        };
+        let rules = parse(
+            "domain;score\na.example.com;90\nb.example.com;10\nc.example.com;50\n",
+            &selector,
+        );
+        assert_eq!(rules.block, ["a.example.com", "c.example.com"]);
+    }
+}
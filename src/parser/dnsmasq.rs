@@ -0,0 +1,107 @@
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+use super::Rules;
+use crate::cli;
+
+pub(super) fn is_signature(line: &str) -> bool {
+    ["address=/", "server=/", "local=/"]
+        .iter()
+        .any(|prefix| line.starts_with(prefix))
+}
+
+// `address=/a/b/ip` answers with the address, a sinkhole, `#` or nothing blocks and any other
+// address is a redirect. `local=/a/` and `server=/a/` answer locally only when no upstream follows
+fn parse_line<'a>(line: &'a str, rules: &mut Rules<'a>) {
+    // Only whole lines are comments, `#` is a valid target as in `server=/example.com/#`
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return;
+    }
+    // Other directives, e.g. `cache-size=1000`, say nothing about names
+    let Some((directive, rest)) = line.split_once('=') else {
+        return;
+    };
+    let Some((names, target)) = rest
+        .strip_prefix('/')
+        .and_then(|rest| rest.rsplit_once('/'))
+    else {
+        return;
+    };
+    let names = names.split('/').filter(|name| !name.is_empty());
+    let target = target.trim();
+    match directive.trim() {
+        "address" if target.is_empty() || target == "#" => {
+            rules.block.extend(names.map(Cow::Borrowed))
+        }
+        "address" => match target.parse::<IpAddr>() {
+            Ok(ip) if cli::CLI.sinkhole_addresses.contains(&ip) => {
+                rules.block.extend(names.map(Cow::Borrowed))
+            }
+            Ok(ip) => rules
+                .overrides
+                .extend(names.map(|name| (Cow::Borrowed(name), ip))),
+            Err(_) => rules.invalid.push(Cow::Borrowed(line)),
+        },
+        "local" | "server" if target.is_empty() => rules.block.extend(names.map(Cow::Borrowed)),
+        _ => {}
+    }
+}
+
+pub(super) fn parse(body: &str) -> Rules<'_> {
+    let mut rules = Rules::default();
+    for line in body.lines() {
+        parse_line(line, &mut rules);
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sinkholes_block_and_other_addresses_redirect() {
+        let rules = parse(
+            "address=/ads.example.com/0.0.0.0\n\
+             address=/a.example.com/b.example.com/\n\
+             address=/hash.example.com/#\n\
+             address=/intranet.example.com/203.0.113.5\n\
+             address=/bad.example.com/not-an-ip\n",
+        );
+        assert_eq!(
+            rules.block,
+            [
+                "ads.example.com",
+                "a.example.com",
+                "b.example.com",
+                "hash.example.com"
+            ]
+        );
+        let overrides = rules
+            .overrides
+            .iter()
+            .map(|(name, ip)| (name.as_ref(), *ip))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            overrides,
+            [("intranet.example.com", "203.0.113.5".parse().unwrap())]
+        );
+        assert_eq!(rules.invalid, ["address=/bad.example.com/not-an-ip"]);
+    }
+
+    #[test]
+    fn servers_only_block_without_an_upstream() {
+        let rules = parse(
+            "server=/local.example.com/\n\
+             server=/fwd.example.com/1.1.1.1\n\
+             server=/ok.example.com/#\n\
+             local=/l.example.com/\n\
+             # address=/commented.example.com/\n\
+             cache-size=1000\n",
+        );
+        assert_eq!(rules.block, ["local.example.com", "l.example.com"]);
+        assert!(rules.overrides.is_empty());
+        assert!(rules.invalid.is_empty());
+    }
+}
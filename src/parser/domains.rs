@@ -0,0 +1,56 @@
+use std::borrow::Cow;
+
+use super::{strip_comment, Rules};
+
+pub(super) fn is_wildcard_signature(line: &str) -> bool {
+    line.starts_with("*.")
+}
+
+// One name per line, `*.` in front only allowed in wildcard lists
+pub(super) fn parse(body: &str, wildcard: bool) -> Rules<'_> {
+    let mut rules = Rules::default();
+    for line in body.lines() {
+        let Some(name) = strip_comment(line, '#').split_whitespace().next() else {
+            continue;
+        };
+        let name = match name.strip_prefix("*.") {
+            Some(name) if wildcard => name,
+            Some(_) => {
+                rules.invalid.push(Cow::Borrowed(line.trim()));
+                continue;
+            }
+            None => name,
+        };
+        if name.contains('*') {
+            rules.invalid.push(Cow::Borrowed(line.trim()));
+        } else {
+            rules.block.push(Cow::Borrowed(name));
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_name_per_line() {
+        let rules = parse(
+            "a.example.com # comment\n\n# b.example.com\nc.example.com extra\n",
+            false,
+        );
+        assert_eq!(rules.block, ["a.example.com", "c.example.com"]);
+    }
+
+    #[test]
+    fn wildcards_only_lead_in_wildcard_lists() {
+        let body = "*.a.example.com\nb.*.example.com\n";
+        let rules = parse(body, true);
+        assert_eq!(rules.block, ["a.example.com"]);
+        assert_eq!(rules.invalid, ["b.*.example.com"]);
+        let rules = parse(body, false);
+        assert!(rules.block.is_empty());
+        assert_eq!(rules.invalid.len(), 2);
+    }
+}
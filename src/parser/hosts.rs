@@ -0,0 +1,104 @@
+use std::borrow::Cow;
+use std::net::IpAddr;
+
+use super::{strip_comment, Rules};
+use crate::cli;
+
+// Names every hosts file carries for the machine itself, never worth blocking
+static RESERVED_NAMES: [&str; 5] = [
+    "localhost",
+    "localhost.localdomain",
+    "local",
+    "broadcasthost",
+    "0.0.0.0",
+];
+
+fn is_ip(token: &str) -> bool {
+    token.parse::<IpAddr>().is_ok()
+}
+
+fn is_reserved(name: &str) -> bool {
+    let name = name.to_lowercase();
+    RESERVED_NAMES.contains(&name.as_str()) || name.starts_with("ip6-")
+}
+
+pub(super) fn is_signature(line: &str) -> bool {
+    let mut tokens = line.split_whitespace();
+    matches!((tokens.next(), tokens.next()), (Some(ip), Some(_)) if is_ip(ip))
+}
+
+// `ip name...`, every name of a sinkhole line is blocked and the names of any other address
+// are redirects. Lines holding a bare name are taken as they come
+pub(super) fn parse(body: &str) -> Rules<'_> {
+    let mut rules = Rules::default();
+    for line in body.lines() {
+        let mut tokens = strip_comment(line, '#').split_whitespace();
+        let Some(first) = tokens.next() else {
+            continue;
+        };
+        let Ok(ip) = first.parse::<IpAddr>() else {
+            if tokens.next().is_some() {
+                rules.invalid.push(Cow::Borrowed(line.trim()));
+            } else if !is_reserved(first) {
+                rules.block.push(Cow::Borrowed(first));
+            }
+            continue;
+        };
+        let mut tokens = tokens.peekable();
+        // An address without names
+        if tokens.peek().is_none() {
+            rules.invalid.push(Cow::Borrowed(line.trim()));
+            continue;
+        }
+        let names = tokens.filter(|name| !is_reserved(name));
+        if cli::CLI.sinkhole_addresses.contains(&ip) {
+            rules.block.extend(names.map(Cow::Borrowed));
+        } else {
+            rules
+                .overrides
+                .extend(names.map(|name| (Cow::Borrowed(name), ip)));
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_name_of_a_sinkhole_line_is_blocked() {
+        let rules = parse("0.0.0.0 a.example.com b.example.com # comment\n:: c.example.com\n");
+        assert_eq!(
+            rules.block,
+            ["a.example.com", "b.example.com", "c.example.com"]
+        );
+        assert!(rules.overrides.is_empty());
+    }
+
+    #[test]
+    fn other_addresses_are_redirects() {
+        let rules = parse("203.0.113.5 intranet.example.com wiki.example.com\n");
+        let overrides = rules
+            .overrides
+            .iter()
+            .map(|(name, ip)| (name.as_ref(), *ip))
+            .collect::<Vec<_>>();
+        let ip = "203.0.113.5".parse().unwrap();
+        assert_eq!(
+            overrides,
+            [("intranet.example.com", ip), ("wiki.example.com", ip)]
+        );
+        assert!(rules.block.is_empty());
+    }
+
+    #[test]
+    fn reserved_names_are_dropped_and_bad_lines_counted() {
+        let rules = parse(
+            "127.0.0.1 localhost\n::1 ip6-localhost ip6-loopback\n0.0.0.0 0.0.0.0\n\
+             bare.example.com\n0.0.0.0\nnot an address\n",
+        );
+        assert_eq!(rules.block, ["bare.example.com"]);
+        assert_eq!(rules.invalid, ["0.0.0.0", "not an address"]);
+    }
+}
@@ -0,0 +1,282 @@
+use serde_json::Value;
+use std::borrow::Cow;
+use std::str::FromStr;
+use tracing::warn;
+
+use super::{Condition, Rules, Selector, NAME_FIELDS};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    // An object member, or an array item when the key is a number
+    Key(String),
+    Wildcard,
+    // The key at any depth, `..domain`
+    Descend(String),
+}
+
+// A JSONPath such as `$.data[*].domain` or `$..domain`, or a JSON pointer such as `/data/0/domain`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JsonPath(Vec<Segment>);
+
+// The name up to the next segment and the rest of the path
+fn split_name(path: &str) -> (&str, &str) {
+    path.split_at(path.find(['.', '[']).unwrap_or(path.len()))
+}
+
+impl FromStr for JsonPath {
+    type Err = String;
+
+    fn from_str(path: &str) -> Result<Self, String> {
+        if let Some(pointer) = path.strip_prefix('/') {
+            let segments = pointer
+                .split('/')
+                .map(|token| Segment::Key(token.replace("~1", "/").replace("~0", "~")))
+                .collect();
+            return Ok(JsonPath(segments));
+        }
+        let mut rest = path.strip_prefix('$').unwrap_or(path);
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            let segment;
+            if let Some(after) = rest.strip_prefix("..") {
+                let (name, next) = split_name(after);
+                if name.is_empty() || name == "*" {
+                    return Err(format!("Expected a key after .. in {path}"));
+                }
+                segment = Segment::Descend(name.to_owned());
+                rest = next;
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let Some((inner, next)) = after.split_once(']') else {
+                    return Err(format!("Unclosed [ in {path}"));
+                };
+                segment = match inner.trim_matches(['\'', '"']) {
+                    "*" => Segment::Wildcard,
+                    "" => return Err(format!("Empty [] in {path}")),
+                    key => Segment::Key(key.to_owned()),
+                };
+                rest = next;
+            } else {
+                // The first key may come without a dot, `data[*].domain`
+                let after = match rest.strip_prefix('.') {
+                    Some(after) => after,
+                    None if segments.is_empty() => rest,
+                    None => return Err(format!("Unexpected {rest} in {path}")),
+                };
+                let (name, next) = split_name(after);
+                segment = match name {
+                    "" => return Err(format!("Expected a key in {path}")),
+                    "*" => Segment::Wildcard,
+                    key => Segment::Key(key.to_owned()),
+                };
+                rest = next;
+            }
+            segments.push(segment);
+        }
+        Ok(JsonPath(segments))
+    }
+}
+
+fn descend<'a>(value: &'a Value, key: &str, found: &mut Vec<&'a Value>) {
+    match value {
+        Value::Object(map) => {
+            if let Some(value) = map.get(key) {
+                found.push(value);
+            }
+            for child in map.values() {
+                descend(child, key, found);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                descend(item, key, found);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn select<'a>(segments: &[Segment], root: &'a Value) -> Vec<&'a Value> {
+    let mut values = vec![root];
+    for segment in segments {
+        let mut selected = Vec::new();
+        for value in values {
+            match (segment, value) {
+                (Segment::Key(key), Value::Object(map)) => selected.extend(map.get(key)),
+                (Segment::Key(key), Value::Array(items)) => {
+                    selected.extend(key.parse::<usize>().ok().and_then(|i| items.get(i)))
+                }
+                (Segment::Wildcard, Value::Object(map)) => selected.extend(map.values()),
+                (Segment::Wildcard, Value::Array(items)) => selected.extend(items),
+                (Segment::Descend(key), value) => descend(value, key, &mut selected),
+                _ => {}
+            }
+        }
+        values = selected;
+    }
+    values
+}
+
+// A condition field may reach into nested objects, `meta.confidence`
+fn holds(object: &Value, conditions: &[Condition]) -> bool {
+    conditions.iter().all(|condition| {
+        let field = condition
+            .field
+            .split('.')
+            .try_fold(object, |value, key| value.get(key));
+        match field {
+            Some(Value::String(value)) => condition.holds(value),
+            Some(Value::Number(value)) => condition.holds(&value.to_string()),
+            Some(Value::Bool(value)) => condition.holds(&value.to_string()),
+            _ => false,
+        }
+    })
+}
+
+// Strings matched by the path become names, as do the strings of a matched array.
+// Conditions look at the records the last wildcard of the path picks, `$.data[*]` in
+// `$.data[*].indicator.value`, or at the object holding the name when there is no wildcard
+pub(super) fn parse<'a>(body: &'a str, selector: &Selector) -> Rules<'a> {
+    let mut rules = Rules::default();
+    let root = match serde_json::from_str::<Value>(body) {
+        Ok(root) => root,
+        Err(e) => {
+            warn!(error = %e, "Error parsing JSON source");
+            return rules;
+        }
+    };
+    let conditions = &selector.conditions;
+    let names: Vec<_> = match &selector.path {
+        Some(JsonPath(segments)) => {
+            let split = segments
+                .iter()
+                .rposition(|segment| matches!(segment, Segment::Wildcard | Segment::Descend(_)))
+                .map_or(segments.len().saturating_sub(1), |i| i + 1);
+            let (records, rest) = segments.split_at(split);
+            select(records, &root)
+                .into_iter()
+                .filter(|record| conditions.is_empty() || holds(record, conditions))
+                .flat_map(|record| select(rest, record))
+                .collect()
+        }
+        // Without a path, an array of names or of objects with a name field
+        None => {
+            let items = match &root {
+                Value::Array(items) => items.iter().collect(),
+                _ => vec![&root],
+            };
+            items
+                .into_iter()
+                .filter(|item| conditions.is_empty() || holds(item, conditions))
+                .filter_map(|item| match item {
+                    Value::Object(map) => NAME_FIELDS.iter().find_map(|field| map.get(*field)),
+                    _ => Some(item),
+                })
+                .collect()
+        }
+    };
+    for name in names {
+        match name {
+            Value::String(name) => rules.block.push(Cow::Owned(name.to_owned())),
+            Value::Array(items) => {
+                for item in items {
+                    match item {
+                        Value::String(name) => rules.block.push(Cow::Owned(name.to_owned())),
+                        _ => rules.skipped += 1,
+                    }
+                }
+            }
+            _ => rules.skipped += 1,
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_path(path: &str) -> Selector {
+        Selector {
+            path: Some(path.parse().unwrap()),
+            ..Selector::default()
+        }
+    }
+
+    #[test]
+    fn paths_and_pointers_parse() {
+        let key = |key: &str| Segment::Key(key.to_owned());
+        assert_eq!(
+            "$.data[*].domain".parse::<JsonPath>(),
+            Ok(JsonPath(vec![
+                key("data"),
+                Segment::Wildcard,
+                key("domain")
+            ]))
+        );
+        assert_eq!(
+            "data['items'][0]".parse::<JsonPath>(),
+            Ok(JsonPath(vec![key("data"), key("items"), key("0")]))
+        );
+        assert_eq!(
+            "$..domain".parse::<JsonPath>(),
+            Ok(JsonPath(vec![Segment::Descend("domain".to_owned())]))
+        );
+        assert_eq!(
+            "/data/0/a~1b".parse::<JsonPath>(),
+            Ok(JsonPath(vec![key("data"), key("0"), key("a/b")]))
+        );
+        assert!("$.data[*".parse::<JsonPath>().is_err());
+        assert!("$..*".parse::<JsonPath>().is_err());
+    }
+
+    #[test]
+    fn paths_select_strings_and_arrays_of_strings() {
+        let body = r#"{"data": [
+            {"domain": "a.example.com", "aliases": ["b.example.com", 1]},
+            {"domain": "c.example.com", "aliases": []},
+            {"domain": 7}
+        ]}"#;
+        let rules = parse(body, &with_path("$.data[*].domain"));
+        assert_eq!(rules.block, ["a.example.com", "c.example.com"]);
+        assert_eq!(rules.skipped, 1);
+        let rules = parse(body, &with_path("$..aliases"));
+        assert_eq!(rules.block, ["b.example.com"]);
+        let rules = parse(body, &with_path("/data/1/domain"));
+        assert_eq!(rules.block, ["c.example.com"]);
+    }
+
+    #[test]
+    fn conditions_look_at_the_records_of_the_last_wildcard() {
+        let body = r#"{"data": [
+            {"indicator": {"value": "a.example.com"}, "meta": {"confidence": 90}},
+            {"indicator": {"value": "b.example.com"}, "meta": {"confidence": 20}}
+        ]}"#;
+        let selector = Selector {
+            conditions: vec!["meta.confidence>=50".parse().unwrap()],
+            ..with_path("$.data[*].indicator.value")
+        };
+        assert_eq!(parse(body, &selector).block, ["a.example.com"]);
+    }
+
+    #[test]
+    fn condition_fields_keep_their_case() {
+        let body = r#"[
+            {"Domain": "a.example.com", "threatType": "Malware"},
+            {"Domain": "b.example.com", "threatType": "phishing"}
+        ]"#;
+        let selector = Selector {
+            conditions: vec!["threatType=malware".parse().unwrap()],
+            ..with_path("$[*].Domain")
+        };
+        assert_eq!(parse(body, &selector).block, ["a.example.com"]);
+    }
+
+    #[test]
+    fn without_a_path_names_come_from_known_fields() {
+        let rules = parse(
+            r#"["a.example.com", {"hostname": "b.example.com"}, {"other": "x"}]"#,
+            &Selector::default(),
+        );
+        assert_eq!(rules.block, ["a.example.com", "b.example.com"]);
+    }
+}
@@ -0,0 +1,168 @@
+use chrono::Utc;
+use serde_json::Value;
+use std::borrow::Cow;
+
+use super::Rules;
+
+// Attribute types holding a name, `domain|ip` holds it before the bar
+static NAME_TYPES: [&str; 3] = ["domain", "hostname", "domain|ip"];
+
+// Values of the `estimative-language:confidence-in-analytic-judgment` tag on the Low/Med/High
+// scale of STIX 2.1, so one threshold serves both formats
+static CONFIDENCE_TAGS: [(&str, u64); 3] = [("low", 15), ("moderate", 50), ("high", 85)];
+
+pub(super) fn is_export(root: &Value) -> bool {
+    events(root)
+        .first()
+        .is_some_and(|event| event["Attribute"].is_array() || event["Object"].is_array())
+}
+
+// A single event, a list of events or the response of the REST search, events or attributes
+fn events(root: &Value) -> Vec<&Value> {
+    let items = match root {
+        Value::Array(items) => items.iter().collect(),
+        _ => match &root["response"] {
+            Value::Array(items) => items.iter().collect(),
+            Value::Null => vec![root],
+            response => vec![response],
+        },
+    };
+    items
+        .into_iter()
+        .map(|item| match &item["Event"] {
+            Value::Null => item,
+            event => event,
+        })
+        .collect()
+}
+
+fn confidence(tags: &Value) -> Option<u64> {
+    tags.as_array()?.iter().find_map(|tag| {
+        let name = tag["name"].as_str()?;
+        let value = name
+            .strip_prefix("estimative-language:confidence-in-analytic-judgment=")?
+            .trim_matches('"');
+        CONFIDENCE_TAGS
+            .iter()
+            .find(|(level, _)| *level == value)
+            .map(|(_, confidence)| *confidence)
+    })
+}
+
+// MISP marks expiry with a sighting of type 2
+fn is_expired(attribute: &Value, now: i64) -> bool {
+    attribute["Sighting"].as_array().is_some_and(|sightings| {
+        sightings.iter().any(|sighting| {
+            sighting["type"].as_str() == Some("2")
+                && sighting["date_sighting"]
+                    .as_str()
+                    .and_then(|date| date.parse::<i64>().ok())
+                    .is_some_and(|date| date <= now)
+        })
+    })
+}
+
+// `domain` and `hostname` attributes of the events and their objects. Attributes not meant for
+// detection (to_ids false), deleted, expired or less confident than the threshold are left out.
+// The attribute tags decide the confidence, the event tags when it has none
+pub(super) fn parse(root: &Value, min_confidence: u8) -> Rules<'static> {
+    let mut rules = Rules::default();
+    let now = Utc::now().timestamp();
+    for event in events(root) {
+        let event_confidence = confidence(&event["Tag"]);
+        let objects = event["Object"].as_array().map_or(&[][..], Vec::as_slice);
+        let attributes = event["Attribute"]
+            .as_array()
+            .into_iter()
+            .chain(
+                objects
+                    .iter()
+                    .filter_map(|object| object["Attribute"].as_array()),
+            )
+            .flatten();
+        for attribute in attributes {
+            let kind = attribute["type"].as_str().unwrap_or_default();
+            if !NAME_TYPES.contains(&kind) {
+                continue;
+            }
+            let confidence = confidence(&attribute["Tag"]).or(event_confidence);
+            if attribute["to_ids"].as_bool() == Some(false)
+                || attribute["deleted"].as_bool() == Some(true)
+                || is_expired(attribute, now)
+                || confidence.is_some_and(|confidence| confidence < min_confidence as u64)
+            {
+                rules.skipped += 1;
+                continue;
+            }
+            match attribute["value"].as_str() {
+                Some(value) => {
+                    let name = value.split('|').next().unwrap_or_default();
+                    rules.block.push(Cow::Owned(name.to_owned()));
+                }
+                None => rules.skipped += 1,
+            }
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn name_attributes_of_events_and_objects() {
+        let root = json!({"Event": {
+            "Attribute": [
+                {"type": "domain", "value": "a.example.com", "to_ids": true},
+                {"type": "domain|ip", "value": "b.example.com|203.0.113.5", "to_ids": true},
+                {"type": "ip-dst", "value": "203.0.113.6", "to_ids": true},
+                {"type": "domain", "value": "context.example.com", "to_ids": false},
+                {"type": "domain", "value": "deleted.example.com", "deleted": true}
+            ],
+            "Object": [{"Attribute": [{"type": "hostname", "value": "c.example.com"}]}]
+        }});
+        assert!(is_export(&root));
+        let rules = parse(&root, 0);
+        assert_eq!(
+            rules.block,
+            ["a.example.com", "b.example.com", "c.example.com"]
+        );
+        assert_eq!(rules.skipped, 2);
+    }
+
+    #[test]
+    fn attribute_tags_decide_the_confidence_before_event_tags() {
+        let tag = |level: &str| {
+            let name = format!("estimative-language:confidence-in-analytic-judgment=\"{level}\"");
+            json!([{ "name": name }])
+        };
+        let root = json!({"response": [{"Event": {
+            "Tag": tag("low"),
+            "Attribute": [
+                {"type": "domain", "value": "low.example.com"},
+                {"type": "domain", "value": "high.example.com", "Tag": tag("high")}
+            ]
+        }}]});
+        let rules = parse(&root, 50);
+        assert_eq!(rules.block, ["high.example.com"]);
+    }
+
+    #[test]
+    fn expiry_sightings_drop_attributes() {
+        let root = json!([{"Event": {"Attribute": [
+            {
+                "type": "domain",
+                "value": "expired.example.com",
+                "Sighting": [{"type": "2", "date_sighting": "946684800"}]
+            },
+            {
+                "type": "domain",
+                "value": "seen.example.com",
+                "Sighting": [{"type": "0", "date_sighting": "946684800"}]
+            }
+        ]}}]);
+        assert_eq!(parse(&root, 0).block, ["seen.example.com"]);
+    }
+}
@@ -0,0 +1,106 @@
+use super::Underscores;
+use crate::report::Outcome;
+
+// RFC 1035 limits, the total without the trailing dot
+static MAX_LABEL_LENGTH: usize = 63;
+static MAX_NAME_LENGTH: usize = 253;
+
+// A hostname Gateway takes: at least two labels of letters, digits and inner hyphens, and a top
+// level label that isn't all digits. Underscores are up to the policy, `_dmarc` and some tracking
+// hosts have them though hostnames shouldn't. The name comes lowercased and in its ASCII form
+pub(super) fn validate(name: &str, underscores: Underscores) -> Result<(), Outcome> {
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(Outcome::TooLong);
+    }
+    let mut labels = 0;
+    let mut last = "";
+    for label in name.split('.') {
+        if label.len() > MAX_LABEL_LENGTH {
+            return Err(Outcome::TooLong);
+        }
+        let valid = label.bytes().all(|byte| match byte {
+            b'a'..=b'z' | b'0'..=b'9' | b'-' => true,
+            b'_' => underscores == Underscores::Allow,
+            _ => false,
+        });
+        if label.is_empty() || label.starts_with('-') || label.ends_with('-') || !valid {
+            return Err(Outcome::InvalidSyntax);
+        }
+        labels += 1;
+        last = label;
+    }
+    if labels < 2 {
+        return Err(Outcome::SingleLabel);
+    }
+    if last.contains('_') || last.bytes().all(|byte| byte.is_ascii_digit()) {
+        return Err(Outcome::InvalidSyntax);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hostnames_pass() {
+        for name in [
+            "example.com",
+            "a-b.example.co.uk",
+            "xn--55qx5d.cn",
+            "1.example.com",
+        ] {
+            assert_eq!(validate(name, Underscores::Reject), Ok(()), "{name}");
+        }
+    }
+
+    #[test]
+    fn lengths_are_limited() {
+        let label = "a".repeat(63);
+        assert_eq!(
+            validate(&format!("{label}.com"), Underscores::Allow),
+            Ok(())
+        );
+        assert_eq!(
+            validate(&format!("a{label}.com"), Underscores::Allow),
+            Err(Outcome::TooLong)
+        );
+        let name = [label.as_str(); 4].join(".");
+        assert_eq!(name.len(), 255);
+        assert_eq!(validate(&name, Underscores::Allow), Err(Outcome::TooLong));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for name in [
+            "-a.example.com",
+            "a-.example.com",
+            "a..example.com",
+            "a b.com",
+            "example.123",
+        ] {
+            assert_eq!(
+                validate(name, Underscores::Allow),
+                Err(Outcome::InvalidSyntax),
+                "{name}"
+            );
+        }
+        assert_eq!(
+            validate("localhost", Underscores::Allow),
+            Err(Outcome::SingleLabel)
+        );
+    }
+
+    #[test]
+    fn underscores_follow_the_policy_but_never_in_the_top_level_label() {
+        assert_eq!(validate("_dmarc.example.com", Underscores::Allow), Ok(()));
+        assert_eq!(
+            validate("_dmarc.example.com", Underscores::Reject),
+            Err(Outcome::InvalidSyntax)
+        );
+        assert_eq!(
+            validate("example.c_m", Underscores::Allow),
+            Err(Outcome::InvalidSyntax)
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use super::{strip_comment, Rules};
+
+static CLASSES: [&str; 4] = ["IN", "CH", "HS", "CS"];
+
+// Subzones whose triggers match answers, name servers or clients rather than the queried name
+static NON_QNAME_TRIGGERS: [&str; 4] = ["rpz-ip", "rpz-nsdname", "rpz-nsip", "rpz-client-ip"];
+
+pub(super) fn is_signature(line: &str) -> bool {
+    let tokens = strip_comment(line, ';')
+        .split_whitespace()
+        .collect::<Vec<_>>();
+    match tokens.as_slice() {
+        [directive, ..] if directive.eq_ignore_ascii_case("$ORIGIN") => true,
+        [.., rtype, target] => {
+            rtype.eq_ignore_ascii_case("CNAME")
+                && (*target == "." || *target == "*." || target.to_lowercase().starts_with("rpz-"))
+        }
+        _ => false,
+    }
+}
+
+enum Action {
+    Block,
+    Passthru,
+    // Local data and redirects answer with something else, which a block list can't express
+    Skip,
+}
+
+// The trigger is the owner name without the apex, e.g. `ads.example.com` or `*.example.com`
+fn action(rtype: &str, target: Option<&str>, trigger: &str) -> Action {
+    if !rtype.eq_ignore_ascii_case("CNAME") {
+        return Action::Skip;
+    }
+    match target.map(str::to_lowercase).as_deref() {
+        Some(".") | Some("*.") | Some("rpz-drop.") => Action::Block,
+        Some("rpz-passthru.") => Action::Passthru,
+        // Old zones pass a name through with a CNAME to the trigger itself, without the apex
+        Some(target) if target.strip_suffix('.') == Some(trigger) => Action::Passthru,
+        _ => Action::Skip,
+    }
+}
+
+// The absolute, lowercase form of an owner name, `@` being the origin
+fn absolute(name: &str, origin: &str) -> String {
+    let name = name.to_lowercase();
+    if name == "@" {
+        origin.to_owned()
+    } else if name.ends_with('.') {
+        name
+    } else {
+        format!("{name}.{origin}")
+    }
+}
+
+// The trigger is the owner name without the zone apex, `*.example.com` covering only the subdomains.
+// Gateway lists a name along with its subdomains, so a wildcard only goes through next to the name
+// itself with the same action. On its own it would take in a name the zone never listed
+pub(super) fn parse(body: &str) -> Rules<'_> {
+    let mut rules = Rules::default();
+    let mut origin = String::new();
+    let mut apex: Option<String> = None;
+    let mut owner = String::new();
+    let mut in_parentheses = false;
+    let mut block = HashSet::new();
+    let mut wildcard_block = HashSet::new();
+    let mut passthru = HashSet::new();
+    let mut wildcard_passthru = HashSet::new();
+    for line in body.lines() {
+        let continues_owner = line.starts_with([' ', '\t']);
+        let line = strip_comment(line, ';');
+        // The rest of a record spread over several lines, only ever the SOA in a policy zone
+        if in_parentheses {
+            in_parentheses = !line.contains(')');
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        in_parentheses = line.contains('(') && !line.contains(')');
+        let tokens = line.split_whitespace().collect::<Vec<_>>();
+        if tokens[0].starts_with('$') {
+            if tokens[0].eq_ignore_ascii_case("$ORIGIN") {
+                if let Some(name) = tokens.get(1) {
+                    origin = absolute(name, &origin);
+                    apex.get_or_insert_with(|| origin.to_owned());
+                }
+            }
+            continue;
+        }
+        let fields = if continues_owner {
+            &tokens[..]
+        } else {
+            owner = absolute(tokens[0], &origin);
+            &tokens[1..]
+        };
+        let mut fields = fields.iter().skip_while(|field| {
+            field.starts_with(|c: char| c.is_ascii_digit())
+                || CLASSES
+                    .iter()
+                    .any(|class| field.eq_ignore_ascii_case(class))
+        });
+        let Some(rtype) = fields.next() else {
+            rules.invalid.push(Cow::Borrowed(line));
+            continue;
+        };
+        if rtype.eq_ignore_ascii_case("SOA") {
+            apex = Some(owner.to_owned());
+            continue;
+        }
+        let trigger = match &apex {
+            // NS and other records of the zone itself
+            Some(apex) if owner == *apex => continue,
+            Some(apex) => match owner
+                .strip_suffix(apex.as_str())
+                .and_then(|name| name.strip_suffix('.'))
+            {
+                Some(name) => name,
+                None => {
+                    rules.skipped += 1;
+                    continue;
+                }
+            },
+            None => owner.trim_end_matches('.'),
+        };
+        if NON_QNAME_TRIGGERS
+            .iter()
+            .any(|subzone| trigger == *subzone || trigger.ends_with(&format!(".{subzone}")))
+        {
+            rules.skipped += 1;
+            continue;
+        }
+        let (name, wildcard) = match trigger.strip_prefix("*.") {
+            Some(name) => (name, true),
+            None => (trigger, false),
+        };
+        let names = match (action(rtype, fields.next().copied(), trigger), wildcard) {
+            (Action::Block, false) => &mut block,
+            (Action::Block, true) => &mut wildcard_block,
+            (Action::Passthru, false) => &mut passthru,
+            (Action::Passthru, true) => &mut wildcard_passthru,
+            (Action::Skip, _) => {
+                rules.skipped += 1;
+                continue;
+            }
+        };
+        names.insert(name.to_owned());
+    }
+    rules.skipped +=
+        wildcard_block.difference(&block).count() + wildcard_passthru.difference(&passthru).count();
+    rules.block = block.into_iter().map(Cow::Owned).collect();
+    rules.allow = passthru
+        .into_iter()
+        .map(|name| {
+            let subdomains = wildcard_passthru.contains(&name);
+            (Cow::Owned(name), subdomains, false)
+        })
+        .collect();
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted<'a>(names: &'a [Cow<str>]) -> Vec<&'a str> {
+        let mut names = names.iter().map(|name| name.as_ref()).collect::<Vec<_>>();
+        names.sort_unstable();
+        names
+    }
+
+    fn allowed<'a>(rules: &'a Rules) -> Vec<(&'a str, bool)> {
+        let mut allow = rules
+            .allow
+            .iter()
+            .map(|(name, subdomains, _)| (name.as_ref(), *subdomains))
+            .collect::<Vec<_>>();
+        allow.sort_unstable();
+        allow
+    }
+
+    static ZONE: &str = "\
+$TTL 300
+$ORIGIN rpz.example.net.
+@ IN SOA localhost. root.localhost. (
+        1 ; serial
+        3600 600 86400 300 )
+  IN NS localhost.
+bad.com         CNAME .
+*.bad.com       CNAME .
+*.onlysub.io    CNAME .
+dropped.net 300 IN CNAME rpz-drop.
+Abs.Example.rpz.example.net. CNAME .
+outside.example.org. CNAME .
+good.bad.com    CNAME rpz-passthru.
+cdn.wild.org    CNAME rpz-passthru.
+*.cdn.wild.org  CNAME rpz-passthru.
+self.io         CNAME self.io.
+into.zone.io    CNAME into.zone.io.rpz.example.net.
+redirect.com    A 10.0.0.1
+                AAAA ::1
+32.1.0.0.10.rpz-ip CNAME .
+$ORIGIN sub.rpz.example.net.
+deep CNAME .
+";
+
+    #[test]
+    fn triggers_are_owners_without_the_apex() {
+        let rules = parse(ZONE);
+        assert_eq!(
+            sorted(&rules.block),
+            ["abs.example", "bad.com", "deep.sub", "dropped.net"]
+        );
+    }
+
+    #[test]
+    fn passthru_and_self_cname_allow() {
+        let rules = parse(ZONE);
+        assert_eq!(
+            allowed(&rules),
+            [
+                ("cdn.wild.org", true),
+                ("good.bad.com", false),
+                ("self.io", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn local_data_and_other_triggers_are_skipped() {
+        let rules = parse(ZONE);
+        // The CNAME into the zone, two address records, the rpz-ip trigger, the owner outside
+        // the zone and the wildcard without its name
+        assert_eq!(rules.skipped, 6);
+    }
+
+    #[test]
+    fn wildcards_without_their_name_are_skipped() {
+        let rules = parse(
+            "$ORIGIN rpz.example.net.\n\
+             *.example.com CNAME .\n\
+             *.example.org CNAME rpz-passthru.\n\
+             *.example.net CNAME .\n\
+             example.net CNAME rpz-passthru.\n",
+        );
+        assert!(rules.block.is_empty());
+        assert_eq!(allowed(&rules), [("example.net", false)]);
+        assert_eq!(rules.skipped, 3);
+    }
+
+    #[test]
+    fn the_first_origin_is_the_apex_without_a_soa() {
+        let rules = parse("$ORIGIN rpz.example.net.\nads.example.com CNAME .\n");
+        assert_eq!(sorted(&rules.block), ["ads.example.com"]);
+    }
+}
@@ -0,0 +1,62 @@
+use std::borrow::Cow;
+
+use super::{strip_comment, Rules};
+
+pub(super) fn is_signature(line: &str) -> bool {
+    (line.starts_with('.') && !line.starts_with("..")) || line.starts_with('{')
+}
+
+// Squid dstdomain entries and Privoxy patterns, `.example.com` covers the subdomains too.
+// Privoxy `{ ... }` lines switch between sections, only patterns under +block are taken
+pub(super) fn parse(body: &str) -> Rules<'_> {
+    let mut blocking = true;
+    let mut rules = Rules::default();
+    for line in body.lines() {
+        let line = strip_comment(line, '#');
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('{') {
+            blocking = line.contains("+block");
+            continue;
+        }
+        if !blocking {
+            continue;
+        }
+        // A pattern with a path only blocks part of the site
+        let (host, path) = line.split_once('/').unwrap_or((line, ""));
+        if !path.is_empty() {
+            rules.skipped += 1;
+            continue;
+        }
+        let host = host.split(':').next().unwrap_or_default();
+        if host.contains('*') {
+            rules.invalid.push(Cow::Borrowed(line));
+        } else {
+            rules.block.push(Cow::Borrowed(host));
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squid_dstdomain_entries() {
+        let rules =
+            parse(".a.example.com\nb.example.com:443\n.c.example.com/path\n*.d.example.com\n");
+        assert_eq!(rules.block, [".a.example.com", "b.example.com"]);
+        assert_eq!(rules.skipped, 1);
+        assert_eq!(rules.invalid, ["*.d.example.com"]);
+    }
+
+    #[test]
+    fn privoxy_patterns_only_under_block_sections() {
+        let rules = parse(
+            "{ +block{Ads} }\n.ads.example.com\n{ -block }\n.ok.example.com\n{+block{Trackers} +handle-as-image}\ntracker.example.com\n",
+        );
+        assert_eq!(rules.block, [".ads.example.com", "tracker.example.com"]);
+    }
+}
@@ -0,0 +1,177 @@
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashSet;
+
+use super::Rules;
+
+// `domain-name:value = 'example.com'`, quotes inside the value are escaped with a backslash
+static DOMAIN_COMPARISON: Lazy<Regex> =
+    Lazy::new(
+        || match Regex::new(r"domain-name:value\s*=\s*'((?:[^'\\]|\\.)*)'") {
+            Ok(re) => re,
+            Err(e) => panic!("Error compiling regex: {}", e),
+        },
+    );
+
+pub(super) fn is_bundle(root: &Value) -> bool {
+    root["type"] == "bundle" && root["objects"].is_array()
+}
+
+fn timestamp(object: &Value, field: &str) -> Option<DateTime<Utc>> {
+    let value = object[field].as_str()?;
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// Revoked, expired, not yet valid or less confident than the threshold
+fn is_dropped(object: &Value, min_confidence: u8, now: DateTime<Utc>) -> bool {
+    object["revoked"].as_bool() == Some(true)
+        || timestamp(object, "valid_until").is_some_and(|until| until <= now)
+        || timestamp(object, "valid_from").is_some_and(|from| from > now)
+        || object["confidence"]
+            .as_u64()
+            .is_some_and(|confidence| confidence < min_confidence as u64)
+}
+
+// The names an indicator pattern compares for equality. Patterns joining comparisons with AND or
+// chaining observations only match together with something a block list can't express
+fn pattern_names(pattern: &str) -> Vec<String> {
+    if [" AND ", "FOLLOWEDBY"]
+        .iter()
+        .any(|operator| pattern.contains(operator))
+    {
+        return Vec::new();
+    }
+    DOMAIN_COMPARISON
+        .captures_iter(pattern)
+        .map(|captures| captures[1].replace("\\'", "'").replace("\\\\", "\\"))
+        .collect()
+}
+
+// `domain-name` observables and `indicator` objects with a STIX pattern. An observable only
+// related to dropped indicators is dropped with them
+pub(super) fn parse(root: &Value, min_confidence: u8) -> Rules<'static> {
+    let mut rules = Rules::default();
+    let now = Utc::now();
+    let objects = root["objects"].as_array().map_or(&[][..], Vec::as_slice);
+    let mut kept = HashSet::new();
+    let mut dropped = HashSet::new();
+    for object in objects {
+        if object["type"] != "indicator" {
+            continue;
+        }
+        let id = object["id"].as_str().unwrap_or_default();
+        if is_dropped(object, min_confidence, now) {
+            dropped.insert(id);
+            rules.skipped += 1;
+            continue;
+        }
+        kept.insert(id);
+        let pattern_type = object["pattern_type"].as_str().unwrap_or("stix");
+        let names = match object["pattern"].as_str() {
+            Some(pattern) if pattern_type == "stix" => pattern_names(pattern),
+            _ => Vec::new(),
+        };
+        if names.is_empty() {
+            rules.skipped += 1;
+        }
+        rules.block.extend(names.into_iter().map(Cow::Owned));
+    }
+    let mut dropped_observables = HashSet::new();
+    let mut kept_observables = HashSet::new();
+    for object in objects
+        .iter()
+        .filter(|object| object["type"] == "relationship")
+    {
+        let ends = [object["source_ref"].as_str(), object["target_ref"].as_str()];
+        if let [Some(source), Some(target)] = ends {
+            for (indicator, observable) in [(source, target), (target, source)] {
+                if dropped.contains(indicator) {
+                    dropped_observables.insert(observable);
+                } else if kept.contains(indicator) {
+                    kept_observables.insert(observable);
+                }
+            }
+        }
+    }
+    for object in objects {
+        if object["type"] != "domain-name" {
+            continue;
+        }
+        let id = object["id"].as_str().unwrap_or_default();
+        if object["revoked"].as_bool() == Some(true)
+            || (dropped_observables.contains(id) && !kept_observables.contains(id))
+        {
+            rules.skipped += 1;
+            continue;
+        }
+        match object["value"].as_str() {
+            Some(name) => rules.block.push(Cow::Owned(name.to_owned())),
+            None => rules.skipped += 1,
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn patterns_give_names_only_for_plain_comparisons() {
+        assert_eq!(
+            pattern_names(
+                "[domain-name:value = 'a.example.com' OR domain-name:value='b\\'s.example.com']"
+            ),
+            ["a.example.com", "b's.example.com"]
+        );
+        assert!(
+            pattern_names("[domain-name:value = 'a.example.com' AND url:value = 'x']").is_empty()
+        );
+        assert!(pattern_names(
+            "[domain-name:value = 'a.example.com'] FOLLOWEDBY [ipv4-addr:value = '1.2.3.4']"
+        )
+        .is_empty());
+    }
+
+    fn indicator(id: &str, name: &str, extra: Value) -> Value {
+        let mut indicator = json!({
+            "type": "indicator",
+            "id": id,
+            "pattern": format!("[domain-name:value = '{name}']"),
+        });
+        if let (Some(indicator), Value::Object(extra)) = (indicator.as_object_mut(), extra) {
+            indicator.extend(extra);
+        }
+        indicator
+    }
+
+    #[test]
+    fn dropped_indicators_take_their_observables_along() {
+        let root = json!({
+            "type": "bundle",
+            "objects": [
+                indicator("indicator--1", "kept.example.com", json!({"confidence": 90})),
+                indicator("indicator--2", "weak.example.com", json!({"confidence": 10})),
+                indicator(
+                    "indicator--3",
+                    "old.example.com",
+                    json!({"valid_until": "2000-01-01T00:00:00Z"})
+                ),
+                indicator("indicator--4", "gone.example.com", json!({"revoked": true})),
+                {"type": "domain-name", "id": "domain-name--1", "value": "observed.example.com"},
+                {"type": "domain-name", "id": "domain-name--2", "value": "related.example.com"},
+                {"type": "relationship", "source_ref": "indicator--2", "target_ref": "domain-name--2"}
+            ]
+        });
+        assert!(is_bundle(&root));
+        let rules = parse(&root, 50);
+        assert_eq!(rules.block, ["kept.example.com", "observed.example.com"]);
+        assert_eq!(rules.skipped, 4);
+    }
+}
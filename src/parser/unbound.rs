@@ -0,0 +1,183 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+
+use super::{strip_comment, Rules};
+use crate::cli;
+
+// Zone types that keep the name from resolving, the rest pass queries through
+static BLOCKING_ZONE_TYPES: [&str; 10] = [
+    "deny",
+    "refuse",
+    "static",
+    "redirect",
+    "inform_deny",
+    "always_refuse",
+    "always_nxdomain",
+    "always_nodata",
+    "always_deny",
+    "always_null",
+];
+
+pub(super) fn is_signature(line: &str) -> bool {
+    line.starts_with("local-zone:") || line.starts_with("local-data:")
+}
+
+// Zone names and record owners compare without case or the trailing dot
+fn key(name: &str) -> String {
+    name.trim_end_matches('.').to_lowercase()
+}
+
+struct Data<'a> {
+    owner: &'a str,
+    // The address of an A or AAAA record, None for other records
+    address: Option<IpAddr>,
+}
+
+// `local-data: "example.com. A 0.0.0.0"`, None when the record can't be read
+fn parse_data(line: &str) -> Option<Data<'_>> {
+    let data = line.strip_prefix("local-data:")?.trim().trim_matches('"');
+    let mut tokens = data.split_whitespace();
+    let owner = tokens.next()?;
+    // A TTL and the class may come before the type
+    let mut tokens = tokens.skip_while(|token| {
+        token.bytes().all(|byte| byte.is_ascii_digit()) || token.eq_ignore_ascii_case("IN")
+    });
+    let record_type = tokens.next()?;
+    let address =
+        if record_type.eq_ignore_ascii_case("A") || record_type.eq_ignore_ascii_case("AAAA") {
+            Some(tokens.next()?.parse::<IpAddr>().ok()?)
+        } else {
+            None
+        };
+    Some(Data { owner, address })
+}
+
+// Blocking zones block their name. A record blocks when it holds a sinkhole address or sits in a
+// blocking zone, a record with any other address is a redirect. A deny, refuse, static or
+// redirect zone answers with its own records, so one with a real address at the apex isn't blocked
+pub(super) fn parse(body: &str) -> Rules<'_> {
+    let mut rules = Rules::default();
+    let mut zones = Vec::new();
+    let mut records = Vec::new();
+    for line in body.lines() {
+        let line = strip_comment(line, '#');
+        if let Some(zone) = line.strip_prefix("local-zone:") {
+            let mut tokens = zone.split_whitespace();
+            match (tokens.next(), tokens.next()) {
+                (Some(name), Some(zone_type)) => zones.push((name.trim_matches('"'), zone_type)),
+                _ => rules.invalid.push(Cow::Borrowed(line)),
+            }
+        } else if line.starts_with("local-data:") {
+            match parse_data(line) {
+                Some(data) => records.push(data),
+                None => rules.invalid.push(Cow::Borrowed(line)),
+            }
+        }
+    }
+    let zone_types = zones
+        .iter()
+        .map(|(name, zone_type)| (key(name), *zone_type))
+        .collect::<HashMap<_, _>>();
+    let mut answered = HashSet::new();
+    for record in records {
+        let owner = key(record.owner);
+        // The closest enclosing zone answers for the record, a record without one is transparent
+        let zone = std::iter::once(owner.as_str())
+            .chain(owner.match_indices('.').map(|(i, _)| &owner[i + 1..]))
+            .find_map(|name| zone_types.get(name).map(|zone_type| (name, *zone_type)));
+        let blocking_zone =
+            zone.is_some_and(|(_, zone_type)| BLOCKING_ZONE_TYPES.contains(&zone_type));
+        match record.address {
+            Some(ip) if cli::CLI.sinkhole_addresses.contains(&ip) => {
+                rules.block.push(Cow::Borrowed(record.owner))
+            }
+            Some(ip) => {
+                if let Some((name, zone_type)) = zone.filter(|(name, _)| *name == owner) {
+                    if !zone_type.starts_with("always_") {
+                        answered.insert(name.to_owned());
+                    }
+                }
+                rules.overrides.push((Cow::Borrowed(record.owner), ip));
+            }
+            None if blocking_zone => rules.block.push(Cow::Borrowed(record.owner)),
+            None => rules.skipped += 1,
+        }
+    }
+    for (name, zone_type) in zones {
+        if BLOCKING_ZONE_TYPES.contains(&zone_type) && !answered.contains(&key(name)) {
+            rules.block.push(Cow::Borrowed(name));
+        }
+    }
+    rules
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn overrides<'a>(rules: &'a Rules) -> Vec<(&'a str, IpAddr)> {
+        rules
+            .overrides
+            .iter()
+            .map(|(name, ip)| (name.as_ref(), *ip))
+            .collect()
+    }
+
+    #[test]
+    fn blocking_zones_block() {
+        let rules = parse(
+            "server:\n\
+             \x20 local-zone: \"ads.example.com.\" always_nxdomain\n\
+             \x20 local-zone: \"ok.example.com.\" transparent\n",
+        );
+        assert_eq!(rules.block, ["ads.example.com."]);
+    }
+
+    #[test]
+    fn records_block_on_a_sinkhole_and_redirect_otherwise() {
+        let rules = parse(
+            "local-data: \"sink.example.com. A 0.0.0.0\"\n\
+             local-data: \"real.example.com. 3600 IN A 198.51.100.7\"\n\
+             local-data: \"www.ok.example.com. CNAME other.example.\"\n\
+             local-data: \"broken.example.com. A nope\"\n",
+        );
+        assert_eq!(rules.block, ["sink.example.com."]);
+        assert_eq!(
+            overrides(&rules),
+            [("real.example.com.", "198.51.100.7".parse().unwrap())]
+        );
+        assert_eq!(rules.skipped, 1);
+        assert_eq!(
+            rules.invalid,
+            ["local-data: \"broken.example.com. A nope\""]
+        );
+    }
+
+    #[test]
+    fn zones_answering_with_a_real_address_are_redirects() {
+        let rules = parse(
+            "local-zone: \"redir.example.com.\" redirect\n\
+             local-data: \"redir.example.com. A 198.51.100.8\"\n\
+             local-zone: \"null.example.com.\" always_null\n\
+             local-data: \"null.example.com. A 198.51.100.9\"\n\
+             local-zone: \"static.example.com.\" static\n\
+             local-data: \"txt.static.example.com. TXT hello\"\n",
+        );
+        assert_eq!(
+            rules.block,
+            [
+                "txt.static.example.com.",
+                "null.example.com.",
+                "static.example.com."
+            ]
+        );
+        assert_eq!(
+            overrides(&rules),
+            [
+                ("redir.example.com.", "198.51.100.8".parse().unwrap()),
+                ("null.example.com.", "198.51.100.9".parse().unwrap())
+            ]
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+use crate::cache;
+use crate::cli;
+
+// Only the start of the body is looked at, lists never open with markup
+static HTML_SNIFF_BYTES: usize = 1024;
+
+// Domains of the last accepted copy live next to the download cache
+fn path(list: &str, source: &str) -> Option<PathBuf> {
+    let dir = cache::dir()?;
+    Some(
+        dir.join("accepted")
+            .join(format!("{}.txt", cache::key(&format!("{list}\n{source}")))),
+    )
+}
+
+pub async fn load(list: &str, source: &str) -> Option<HashSet<String>> {
+    let content = tokio::fs::read_to_string(path(list, source)?).await.ok()?;
+    Some(content.lines().map(|line| line.to_owned()).collect())
+}
+
+fn pending(path: &Path) -> PathBuf {
+    path.with_extension("txt.pending")
+}
+
+// Writes the new accepted copy next to the current one, `commit` puts it in place once the run
+// went through. A failed or aborted run leaves the last accepted copies as they were
+pub async fn stage(list: &str, source: &str, domains: &HashSet<String>) -> Option<PathBuf> {
+    let path = path(list, source)?;
+    let mut sorted = domains.iter().map(|d| d.as_str()).collect::<Vec<_>>();
+    sorted.sort_unstable();
+    let mut content = sorted.join("\n");
+    content.push('\n');
+    let result = async {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::write(pending(&path), content).await
+    }
+    .await;
+    match result {
+        Ok(()) => Some(path),
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "Error writing accepted copy");
+            None
+        }
+    }
+}
+
+pub async fn commit(staged: &[PathBuf]) {
+    for path in staged {
+        if let Err(e) = tokio::fs::rename(pending(path), path).await {
+            warn!(path = %path.display(), error = %e, "Error writing accepted copy");
+        }
+    }
+}
+
+fn looks_like_html(body: &str) -> bool {
+    let mut end = body.len().min(HTML_SNIFF_BYTES);
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    let head = body[..end].to_lowercase();
+    ["<!doctype html", "<html", "<head>", "<body"]
+        .iter()
+        .any(|tag| head.contains(tag))
+}
+
+// Returns why the new copy of a source can't be trusted, compared to the last accepted one
+pub fn inspect(
+    body: &str,
+    domains: &HashSet<String>,
+    previous: Option<&HashSet<String>>,
+) -> Option<String> {
+    if looks_like_html(body) {
+        return Some("Body looks like an HTML page".to_owned());
+    }
+    let previous = previous.filter(|previous| !previous.is_empty())?;
+    if domains.is_empty() {
+        return Some(format!("No domains left, previously {}", previous.len()));
+    }
+    // Small lists swing by large percentages on ordinary updates
+    if previous.len() < cli::CLI.quarantine_min_domains {
+        return None;
+    }
+    let percent = |count: usize| count as f64 / previous.len() as f64 * 100.0;
+    let removed = percent(previous.difference(domains).count());
+    if removed > cli::CLI.quarantine_max_removed {
+        return Some(format!(
+            "{removed:.1}% of the previous {} domains are gone, more than the allowed {}%",
+            previous.len(),
+            cli::CLI.quarantine_max_removed
+        ));
+    }
+    let added = percent(domains.difference(previous).count());
+    if added > cli::CLI.quarantine_max_added {
+        return Some(format!(
+            "Grew by {added:.1}% of the previous {} domains, more than the allowed {}%",
+            previous.len(),
+            cli::CLI.quarantine_max_added
+        ));
+    }
+    None
+}
@@ -0,0 +1,485 @@
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use tracing::{error, info};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RunStatus {
+    Running,
+    Success,
+    Unchanged,
+    Failure,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceStatus {
+    #[default]
+    Ok,
+    // Download failed, the last good cached copy was used instead
+    Fallback,
+    // Looked anomalous, the last accepted copy was used instead
+    Quarantined,
+    Failed,
+}
+
+// Samples kept per outcome of a source, enough to spot a pattern
+static OUTCOME_SAMPLES: usize = 5;
+
+// What became of a line or a name of a source. Blank, comment and invalid syntax count lines,
+// the rest count names, a hosts line can hold several
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Accepted,
+    Blank,
+    Comment,
+    InvalidSyntax,
+    // Over 63 characters in a label or 253 in the name
+    TooLong,
+    // A bare word such as `localhost`, blocking a top level domain is never meant
+    SingleLabel,
+    IpLiteral,
+    Idna,
+    // A blocked name such as `co.uk` or `github.io` would take down every site under it
+    PublicSuffix,
+    Whitelisted,
+    Duplicate,
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Outcomes {
+    pub counts: BTreeMap<Outcome, usize>,
+    pub samples: BTreeMap<Outcome, Vec<String>>,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Accepted => "accepted",
+            Outcome::Blank => "blank",
+            Outcome::Comment => "comment",
+            Outcome::InvalidSyntax => "invalid_syntax",
+            Outcome::TooLong => "too_long",
+            Outcome::SingleLabel => "single_label",
+            Outcome::IpLiteral => "ip_literal",
+            Outcome::Idna => "idna",
+            Outcome::PublicSuffix => "public_suffix",
+            Outcome::Whitelisted => "whitelisted",
+            Outcome::Duplicate => "duplicate",
+        }
+    }
+}
+
+impl Outcomes {
+    pub fn record(&mut self, outcome: Outcome, sample: &str) {
+        self.count(outcome, 1);
+        let samples = self.samples.entry(outcome).or_default();
+        if samples.len() < OUTCOME_SAMPLES {
+            samples.push(sample.to_owned());
+        }
+    }
+
+    // For outcomes whose lines say nothing, like blank ones
+    pub fn count(&mut self, outcome: Outcome, count: usize) {
+        if count > 0 {
+            *self.counts.entry(outcome).or_default() += count;
+        }
+    }
+
+    // `accepted 120, comment 4`, in the order of the outcomes
+    pub fn summary(&self) -> String {
+        self.counts
+            .iter()
+            .map(|(outcome, count)| format!("{} {count}", outcome.as_str()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct SourceReport {
+    pub list: String,
+    pub url: String,
+    pub status: SourceStatus,
+    pub error: Option<String>,
+    pub weight: f64,
+    // Parser used for the body, given in the list file or detected
+    pub format: String,
+    // Text encoding the body was read with, and what had to be repaired or guessed to read it
+    pub encoding: String,
+    pub decode_issue: Option<String>,
+    pub bytes: usize,
+    pub lines: usize,
+    pub domains: usize,
+    // Domains in the last accepted copy, None before the first run with a cache directory
+    pub previous_domains: Option<usize>,
+    pub whitelisted: usize,
+    pub unique: usize,
+    // Exception rules, e.g. `@@||example.com^`, found in the source
+    pub exceptions: usize,
+    // Hosts lines pointing a name at an address other than a sinkhole
+    pub overrides: usize,
+    // Rules that can't be expressed in a DNS block list, e.g. $client or URL rules
+    pub skipped_rules: usize,
+    pub outcomes: Outcomes,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct Totals {
+    pub allow: usize,
+    pub block: usize,
+    pub whitelisted: usize,
+    pub collapsed_subdomains: usize,
+    // Blocked names that are public suffixes, e.g. co.uk, never synced
+    pub public_suffixes: usize,
+    // Names allowed by exception rules in block list sources, and the blocked names they removed
+    pub exceptions: usize,
+    pub excepted: usize,
+    // Exceptions below a name that stays blocked, Gateway blocks them anyway
+    pub shadowed_exceptions: usize,
+    // Names left out by $denyallow rules, they only join the allow list, and those Gateway blocks
+    // anyway because they sit below a blocked name
+    pub carve_outs: usize,
+    pub shadowed_carve_outs: usize,
+    // Redirected names, Gateway lists can't carry them so only exports write them out
+    pub overrides: usize,
+    pub skipped_rules: usize,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct SyncReport {
+    pub deployed_before: Option<u64>,
+    pub items_added: Option<usize>,
+    pub items_removed: Option<usize>,
+    pub lists_deleted: usize,
+    pub lists_created: usize,
+    pub lists_failed: usize,
+    pub policies_deleted: i32,
+    pub policy_action: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RunReport {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    pub status: RunStatus,
+    pub error: Option<String>,
+    pub totals: Totals,
+    pub sync: SyncReport,
+    // Seconds spent in each phase
+    pub durations: BTreeMap<String, f64>,
+    pub sources: Vec<SourceReport>,
+}
+
+impl SourceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SourceStatus::Ok => "ok",
+            SourceStatus::Fallback => "fallback",
+            SourceStatus::Quarantined => "quarantined",
+            SourceStatus::Failed => "failed",
+        }
+    }
+}
+
+impl RunStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunStatus::Running => "running",
+            RunStatus::Success => "success",
+            RunStatus::Unchanged => "unchanged",
+            RunStatus::Failure => "failure",
+        }
+    }
+}
+
+impl SourceReport {
+    // Every outcome of the source with its samples, for --explain-source
+    pub fn explain(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "Source {} in {}", self.url, self.list);
+        let _ = writeln!(
+            out,
+            "  status {}, format {}, encoding {}, {} bytes, {} lines",
+            self.status.as_str(),
+            self.format,
+            self.encoding,
+            self.bytes,
+            self.lines
+        );
+        for error in self.error.iter().chain(self.decode_issue.iter()) {
+            let _ = writeln!(out, "  error: {error}");
+        }
+        for (outcome, count) in &self.outcomes.counts {
+            let _ = writeln!(out, "  {:<15} {count:>9}", outcome.as_str());
+            for sample in self.outcomes.samples.get(outcome).into_iter().flatten() {
+                let _ = writeln!(out, "      {sample}");
+            }
+        }
+        let _ = writeln!(out, "  {:<15} {:>9}", "skipped_rules", self.skipped_rules);
+        out
+    }
+}
+
+impl RunReport {
+    pub fn new() -> Self {
+        RunReport {
+            started_at: Utc::now(),
+            finished_at: None,
+            status: RunStatus::Running,
+            error: None,
+            totals: Totals::default(),
+            sync: SyncReport::default(),
+            durations: BTreeMap::new(),
+            sources: Vec::new(),
+        }
+    }
+
+    pub fn finish(&mut self, status: RunStatus, error: Option<String>) {
+        self.finished_at = Some(Utc::now());
+        self.status = status;
+        self.error = error;
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# Sync report {}\n", self.started_at.to_rfc3339());
+        let _ = writeln!(out, "- Status: **{}**", self.status.as_str());
+        if let Some(error) = &self.error {
+            let _ = writeln!(out, "- Error: `{}`", error.replace('`', "'"));
+        }
+        if let Some(finished_at) = self.finished_at {
+            let _ = writeln!(out, "- Finished: {}", finished_at.to_rfc3339());
+        }
+        let _ = writeln!(out, "\n## Summary\n\n| Item | Value |\n| --- | --- |");
+        for (name, value) in self.summary_rows() {
+            let _ = writeln!(out, "| {name} | {value} |");
+        }
+        let _ = writeln!(
+            out,
+            "\n## Sources\n\n| List | Source | Status | Bytes | Lines | Domains | Whitelisted | Unique |\n| --- | --- | --- | ---: | ---: | ---: | ---: | ---: |"
+        );
+        for source in &self.sources {
+            let _ = writeln!(
+                out,
+                "| {} | {} | {} | {} | {} | {} | {} | {} |",
+                source.list,
+                source.url.replace('|', "%7C"),
+                source.status.as_str(),
+                source.bytes,
+                source.lines,
+                source.domains,
+                source.whitelisted,
+                source.unique
+            );
+        }
+        let _ = writeln!(out, "\n## Source outcomes\n");
+        for source in &self.sources {
+            let _ = writeln!(out, "- {}: {}", source.url, source.outcomes.summary());
+        }
+        let mut errors = self.source_errors().peekable();
+        if errors.peek().is_some() {
+            let _ = writeln!(out, "\n## Source errors\n");
+            for (source, error) in errors {
+                let _ = writeln!(
+                    out,
+                    "- {} ({}): `{}`",
+                    source.url,
+                    source.status.as_str(),
+                    error.replace('`', "'")
+                );
+            }
+        }
+        out
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut out = String::new();
+        let title = format!("Sync report {}", self.started_at.to_rfc3339());
+        let _ = write!(
+            out,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{0}</title></head><body>\n<h1>{0}</h1>\n<p>Status: <strong>{1}</strong></p>\n",
+            escape_html(&title),
+            self.status.as_str()
+        );
+        if let Some(error) = &self.error {
+            let _ = writeln!(out, "<p>Error: <code>{}</code></p>", escape_html(error));
+        }
+        let _ = writeln!(out, "<h2>Summary</h2>\n<table>");
+        for (name, value) in self.summary_rows() {
+            let _ = writeln!(
+                out,
+                "<tr><th>{}</th><td>{}</td></tr>",
+                escape_html(name),
+                escape_html(&value)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "</table>\n<h2>Sources</h2>\n<table>\n<tr><th>List</th><th>Source</th><th>Status</th><th>Bytes</th><th>Lines</th><th>Domains</th><th>Whitelisted</th><th>Unique</th></tr>"
+        );
+        for source in &self.sources {
+            let _ = writeln!(
+                out,
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                escape_html(&source.list),
+                escape_html(&source.url),
+                source.status.as_str(),
+                source.bytes,
+                source.lines,
+                source.domains,
+                source.whitelisted,
+                source.unique
+            );
+        }
+        let _ = writeln!(out, "</table>\n<h2>Source outcomes</h2>\n<ul>");
+        for source in &self.sources {
+            let _ = writeln!(
+                out,
+                "<li>{}: {}</li>",
+                escape_html(&source.url),
+                escape_html(&source.outcomes.summary())
+            );
+        }
+        let _ = writeln!(out, "</ul>");
+        let mut errors = self.source_errors().peekable();
+        if errors.peek().is_some() {
+            let _ = writeln!(out, "<h2>Source errors</h2>\n<ul>");
+            for (source, error) in errors {
+                let _ = writeln!(
+                    out,
+                    "<li>{} ({}): <code>{}</code></li>",
+                    escape_html(&source.url),
+                    source.status.as_str(),
+                    escape_html(error)
+                );
+            }
+            let _ = writeln!(out, "</ul>");
+        }
+        let _ = writeln!(out, "</body></html>");
+        out
+    }
+
+    // Download and parse errors, followed by decoding problems of sources that were still read
+    fn source_errors(&self) -> impl Iterator<Item = (&SourceReport, &String)> {
+        self.sources.iter().flat_map(|source| {
+            source
+                .error
+                .iter()
+                .chain(source.decode_issue.iter())
+                .map(move |error| (source, error))
+        })
+    }
+
+    fn summary_rows(&self) -> Vec<(&'static str, String)> {
+        let optional =
+            |value: Option<usize>| value.map_or_else(|| "-".to_owned(), |v| v.to_string());
+        let count = |status: SourceStatus| {
+            self.sources
+                .iter()
+                .filter(|source| source.status == status)
+                .count()
+                .to_string()
+        };
+        let mut rows = vec![
+            ("Allow domains", self.totals.allow.to_string()),
+            ("Block domains", self.totals.block.to_string()),
+            ("Removed by whitelist", self.totals.whitelisted.to_string()),
+            ("Exception rules", self.totals.exceptions.to_string()),
+            ("Removed by exceptions", self.totals.excepted.to_string()),
+            (
+                "Exceptions still blocked by Gateway",
+                self.totals.shadowed_exceptions.to_string(),
+            ),
+            ("Denyallow carve-outs", self.totals.carve_outs.to_string()),
+            (
+                "Carve-outs still blocked by Gateway",
+                self.totals.shadowed_carve_outs.to_string(),
+            ),
+            ("Redirected names", self.totals.overrides.to_string()),
+            ("Skipped rules", self.totals.skipped_rules.to_string()),
+            ("Sources on cached copy", count(SourceStatus::Fallback)),
+            ("Sources quarantined", count(SourceStatus::Quarantined)),
+            ("Sources failed", count(SourceStatus::Failed)),
+            (
+                "Entries saved by collapsing subdomains",
+                self.totals.collapsed_subdomains.to_string(),
+            ),
+            (
+                "Refused public suffixes",
+                self.totals.public_suffixes.to_string(),
+            ),
+            (
+                "Deployed before",
+                self.sync
+                    .deployed_before
+                    .map_or_else(|| "-".to_owned(), |v| v.to_string()),
+            ),
+            ("Items added", optional(self.sync.items_added)),
+            ("Items removed", optional(self.sync.items_removed)),
+            ("Lists deleted", self.sync.lists_deleted.to_string()),
+            ("Lists created", self.sync.lists_created.to_string()),
+            ("Lists failed", self.sync.lists_failed.to_string()),
+            ("Policies deleted", self.sync.policies_deleted.to_string()),
+            (
+                "Policy action",
+                self.sync
+                    .policy_action
+                    .clone()
+                    .unwrap_or_else(|| "-".to_owned()),
+            ),
+        ];
+        for (phase, seconds) in &self.durations {
+            rows.push(("Duration", format!("{phase}: {seconds:.2}s")));
+        }
+        rows
+    }
+
+    pub async fn write(&self, dir: &Path, formats: &[ReportFormat]) {
+        if let Err(e) = tokio::fs::create_dir_all(dir).await {
+            error!(dir = %dir.display(), error = %e, "Error creating report directory");
+            return;
+        }
+        let stem = format!("run-{}", self.started_at.format("%Y%m%dT%H%M%SZ"));
+        let json = match serde_json::to_string_pretty(self) {
+            Ok(json) => json,
+            Err(e) => {
+                error!(error = %e, "Error serializing run report");
+                return;
+            }
+        };
+        let mut outputs = vec![(format!("{stem}.json"), json)];
+        for format in formats {
+            match format {
+                ReportFormat::Markdown => outputs.push((format!("{stem}.md"), self.to_markdown())),
+                ReportFormat::Html => outputs.push((format!("{stem}.html"), self.to_html())),
+            }
+        }
+        for (name, content) in outputs {
+            let path = dir.join(name);
+            match tokio::fs::write(&path, content).await {
+                Ok(_) => info!(path = %path.display(), "Wrote run report"),
+                Err(e) => error!(path = %path.display(), error = %e, "Error writing run report"),
+            }
+        }
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
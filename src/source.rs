@@ -0,0 +1,529 @@
+use chrono::Utc;
+use clap::ValueEnum;
+use encoding_rs::Encoding;
+use once_cell::sync::Lazy;
+use reqwest::{header, Client, StatusCode};
+use std::fmt;
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::{debug, warn, Span};
+use url::Url;
+
+use crate::cache::{self, CacheMeta};
+use crate::cli;
+use crate::decompress::{self, Hints};
+use crate::encoding::{self, Decoded};
+use crate::parser::{Format, Selector};
+use crate::report::SourceStatus;
+
+static CLIENT: Lazy<Client> = Lazy::new(|| {
+    Client::builder()
+        .pool_idle_timeout(Some(Duration::from_secs(600)))
+        .tcp_keepalive(Some(Duration::from_secs(60)))
+        .build()
+        .unwrap()
+});
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Location {
+    Remote(String),
+    Local(PathBuf),
+}
+
+// Settings written after the location as key=value pairs, e.g. `https://example.com/hosts weight=3`
+#[derive(Clone, Debug)]
+pub struct SourceOptions {
+    // Share of this source in the failed source budget, relative to the other sources
+    pub weight: f64,
+    // File to read from a zip archive holding more than one
+    pub member: Option<String>,
+    // Parser to use instead of detecting the format from the content
+    pub format: Option<Format>,
+    // Text encoding to use when the body has no BOM, instead of the declared or guessed one
+    pub charset: Option<&'static Encoding>,
+    // Column, path and conditions of CSV and JSON sources
+    pub selector: Selector,
+}
+
+impl Default for SourceOptions {
+    fn default() -> Self {
+        SourceOptions {
+            weight: 1.0,
+            member: None,
+            format: None,
+            charset: None,
+            selector: Selector::default(),
+        }
+    }
+}
+
+// One entry of lists.txt / whitelists.txt
+#[derive(Clone, Debug)]
+pub struct Source {
+    // The location as written in the list file, used to name the source in logs and reports
+    pub name: String,
+    pub location: Location,
+    pub options: SourceOptions,
+}
+
+// Outcome of fetching one source, body is None only when the source failed without a fallback
+pub struct Fetched {
+    pub body: Option<Decoded>,
+    pub status: SourceStatus,
+    pub error: Option<String>,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl Source {
+    // Local paths are resolved against the directory of the list file, file:// URLs are absolute
+    pub fn parse(line: &str, base_dir: &Path) -> Option<Source> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let mut parts = line.split_whitespace();
+        let line = parts.next()?;
+        let options = parse_options(line, parts);
+        let location = if line.starts_with("file://") {
+            // file://localhost/path is the same as file:///path, a file on another host can't be read
+            match Url::parse(line)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+            {
+                Some(path) => Location::Local(path),
+                None => {
+                    warn!(
+                        source_url = line,
+                        "Ignoring file URL that isn't a local path"
+                    );
+                    return None;
+                }
+            }
+        } else if line.contains("://") {
+            Location::Remote(line.to_owned())
+        } else {
+            Location::Local(base_dir.join(line))
+        };
+        Some(Source {
+            name: line.to_owned(),
+            location,
+            options,
+        })
+    }
+
+    pub async fn fetch(&self) -> Fetched {
+        let result = match &self.location {
+            Location::Remote(url) => download_content(url).await,
+            Location::Local(path) => Ok((Input::File(path.clone()), Hints::default())),
+        };
+        let result = match result {
+            Ok((input, hints)) => self.decode(input, hints).await,
+            Err(error) => Err(error),
+        };
+        let error = match result {
+            Ok(body) => {
+                return Fetched {
+                    body: Some(body),
+                    status: SourceStatus::Ok,
+                    error: None,
+                }
+            }
+            Err(error) => error,
+        };
+        if let Location::Remote(url) = &self.location {
+            if let Some((body, hints)) = cached_fallback(url).await {
+                match self.decode(Input::File(body), hints).await {
+                    Ok(body) => {
+                        warn!(error = %error, "Source failed, using cached copy");
+                        return Fetched {
+                            body: Some(body),
+                            status: SourceStatus::Fallback,
+                            error: Some(error),
+                        };
+                    }
+                    Err(e) => warn!(error = %e, "Cached copy is unusable"),
+                }
+            }
+        }
+        warn!(error = %error, "Source failed");
+        Fetched {
+            body: None,
+            status: SourceStatus::Failed,
+            error: Some(error),
+        }
+    }
+
+    // Decompression and decoding run off the async workers, large feeds take a while to inflate.
+    // A download is fed to them as it arrives. It only replaces the cached copy once it decodes,
+    // the fallback and later conditional requests must never get a body that can't be read
+    async fn decode(&self, input: Input, hints: Hints) -> Result<Decoded, String> {
+        let name = self.name.clone();
+        let member = self.options.member.clone();
+        let charset = self.options.charset;
+        let span = Span::current();
+        let limit = limit();
+        let (reader, download) = match input {
+            Input::File(path) => (Reader::File(path), None),
+            Input::Response(download) => {
+                let (resp, pending) = *download;
+                let (tx, rx) = mpsc::channel(CHUNKS_IN_FLIGHT);
+                (Reader::Chunks(Chunks::new(rx)), Some((resp, tx, pending)))
+            }
+        };
+        let task = tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let body = match reader {
+                Reader::File(path) => match open_local(&path, limit) {
+                    Ok(file) => decompress::decode(&name, file, &hints, member.as_deref(), limit),
+                    Err(e) => Err(e),
+                },
+                Reader::Chunks(chunks) => {
+                    decompress::decode(&name, chunks, &hints, member.as_deref(), limit)
+                }
+            };
+            body.map(|body| {
+                let decoded = encoding::decode(&body, charset, hints.content_type.as_deref());
+                if let Some(issue) = &decoded.issue {
+                    warn!(
+                        encoding = decoded.encoding,
+                        issue, "Source needed repairs to decode"
+                    );
+                }
+                decoded
+            })
+        });
+        // A failed download wins over whatever the decoder made of the part it got
+        let pending = match download {
+            Some((resp, tx, pending)) => feed(resp, tx, pending, limit).await?,
+            None => None,
+        };
+        let decoded = match task.await {
+            Ok(decoded) => decoded?,
+            Err(e) => return Err(format!("Error decompressing: {}", e)),
+        };
+        if let Some(pending) = pending {
+            pending.commit().await;
+        }
+        Ok(decoded)
+    }
+}
+
+// Where the raw body of a source is read from while it's decompressed
+enum Input {
+    File(PathBuf),
+    // A fresh download, written to the cache as it arrives
+    Response(Box<(reqwest::Response, Option<cache::Pending>)>),
+}
+
+enum Reader {
+    File(PathBuf),
+    Chunks(Chunks),
+}
+
+// The chunks of a download as they arrive, read by the decoders on the blocking pool
+struct Chunks {
+    rx: mpsc::Receiver<Vec<u8>>,
+    chunk: Cursor<Vec<u8>>,
+}
+
+impl Chunks {
+    fn new(rx: mpsc::Receiver<Vec<u8>>) -> Self {
+        Chunks {
+            rx,
+            chunk: Cursor::new(Vec::new()),
+        }
+    }
+}
+
+impl Read for Chunks {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.chunk.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                return Ok(read);
+            }
+            match self.rx.blocking_recv() {
+                Some(chunk) => self.chunk = Cursor::new(chunk),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+fn parse_options<'a>(location: &str, parts: impl Iterator<Item = &'a str>) -> SourceOptions {
+    let mut options = SourceOptions::default();
+    for part in parts {
+        match part.split_once('=') {
+            Some(("member", value)) if !value.is_empty() => options.member = Some(value.to_owned()),
+            Some(("format", value)) => match Format::from_str(value, true) {
+                Ok(format) => options.format = Some(format),
+                Err(_) => warn!(
+                    source_url = location,
+                    value, "Ignoring unknown source format"
+                ),
+            },
+            Some(("delimiter", value)) => match value {
+                "tab" | "\\t" => options.selector.delimiter = '\t',
+                _ if value.chars().count() == 1 => {
+                    options.selector.delimiter = value.chars().next().unwrap_or(',')
+                }
+                _ => warn!(
+                    source_url = location,
+                    value, "Ignoring invalid CSV delimiter"
+                ),
+            },
+            Some(("quote", value)) => match value {
+                "none" => options.selector.quote = None,
+                _ if value.chars().count() == 1 => options.selector.quote = value.chars().next(),
+                _ => warn!(source_url = location, value, "Ignoring invalid CSV quote"),
+            },
+            Some(("header", value)) => match value {
+                "yes" | "true" => options.selector.header = Some(true),
+                "no" | "false" => options.selector.header = Some(false),
+                _ => warn!(
+                    source_url = location,
+                    value, "Ignoring invalid CSV header setting"
+                ),
+            },
+            Some(("column", value)) => match value.parse() {
+                Ok(column) => options.selector.column = Some(column),
+                Err(error) => warn!(
+                    source_url = location,
+                    value, error, "Ignoring invalid CSV column"
+                ),
+            },
+            Some(("path", value)) => match value.parse() {
+                Ok(path) => options.selector.path = Some(path),
+                Err(error) => warn!(
+                    source_url = location,
+                    value, error, "Ignoring invalid JSON path"
+                ),
+            },
+            Some(("where", value)) => match value.parse() {
+                Ok(condition) => options.selector.conditions.push(condition),
+                Err(error) => warn!(
+                    source_url = location,
+                    value, error, "Ignoring invalid condition"
+                ),
+            },
+            Some(("charset", value)) => match Encoding::for_label(value.as_bytes()) {
+                Some(charset) => options.charset = Some(charset),
+                None => warn!(source_url = location, value, "Ignoring unknown charset"),
+            },
+            Some(("min_confidence", value)) => match value.parse::<u8>() {
+                Ok(confidence) if confidence <= 100 => {
+                    options.selector.min_confidence = Some(confidence)
+                }
+                _ => warn!(
+                    source_url = location,
+                    value, "Ignoring invalid minimum confidence"
+                ),
+            },
+            Some(("weight", value)) => match value.parse::<f64>() {
+                Ok(weight) if weight >= 0.0 => options.weight = weight,
+                _ => warn!(
+                    source_url = location,
+                    value, "Ignoring invalid source weight"
+                ),
+            },
+            _ => warn!(
+                source_url = location,
+                option = part,
+                "Ignoring unknown source option"
+            ),
+        }
+    }
+    // A column or a path only make sense for one format
+    if options.format.is_none() {
+        if options.selector.column.is_some() {
+            options.format = Some(Format::Csv);
+        } else if options.selector.path.is_some() {
+            options.format = Some(Format::Json);
+        }
+    }
+    options
+}
+
+// Chunks of a download waiting for the decoder, the download holds back once they pile up
+static CHUNKS_IN_FLIGHT: usize = 16;
+
+// Largest size of a source, as downloaded and once decompressed
+fn limit() -> u64 {
+    cli::CLI.max_decompressed_mb.saturating_mul(1024 * 1024)
+}
+
+fn open_local(path: &Path, limit: u64) -> Result<impl Read, String> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) => return Err(format!("Error reading {}: {}", path.display(), e)),
+    };
+    let size = match file.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return Err(format!("Error reading {}: {}", path.display(), e)),
+    };
+    if size > limit {
+        return Err(decompress::exceeded(limit));
+    }
+    debug!(path = %path.display(), size, "Reading local source");
+    Ok(file.take(limit))
+}
+
+// Hands the response to the decoder chunk by chunk and writes it to the cache on the way. Gives up
+// one chunk past the limit. The cache entry comes back once the whole body went through, a
+// decoder stopping early leaves it out
+async fn feed(
+    mut resp: reqwest::Response,
+    tx: mpsc::Sender<Vec<u8>>,
+    mut pending: Option<cache::Pending>,
+    limit: u64,
+) -> Result<Option<cache::Pending>, String> {
+    let mut size = 0;
+    loop {
+        let chunk = match resp.chunk().await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(e) => return Err(format!("Error reading response: {}", e)),
+        };
+        size += chunk.len() as u64;
+        if size > limit {
+            return Err(decompress::exceeded(limit));
+        }
+        if let Some(entry) = pending.as_mut() {
+            if let Err(e) = entry.write(&chunk).await {
+                warn!(error = %e, "Error writing cache entry");
+                pending = None;
+            }
+        }
+        if tx.send(chunk.to_vec()).await.is_err() {
+            return Ok(None);
+        }
+    }
+    debug!(size, "Downloaded");
+    Ok(pending)
+}
+
+// The last good copy, as long as it is younger than --cache-max-age
+async fn cached_fallback(url: &str) -> Option<(PathBuf, Hints)> {
+    let entry = cache::load(url).await?;
+    let age = (Utc::now() - entry.meta.fetched_at)
+        .to_std()
+        .unwrap_or_default();
+    if age > cli::CLI.cache_max_age {
+        warn!(fetched_at = %entry.meta.fetched_at, "Cached copy is too old to fall back to");
+        return None;
+    }
+    let hints = entry.meta.hints();
+    Some((entry.body, hints))
+}
+
+// The body as it is sent, compressed or not, together with what the server said about it. A
+// fresh download is still to be read, a declared Content-Length over the limit fails before that
+async fn download_content(url: &str) -> Result<(Input, Hints), String> {
+    let cached = cache::load(url).await;
+    if cli::CLI.offline {
+        return match cached {
+            Some(entry) => {
+                debug!(fetched_at = %entry.meta.fetched_at, "Using cached copy, offline");
+                let hints = entry.meta.hints();
+                Ok((Input::File(entry.body), hints))
+            }
+            None => Err(format!("No cached copy of {} for offline mode", url)),
+        };
+    }
+
+    let mut request = CLIENT.get(url);
+    if let Some(entry) = &cached {
+        if let Some(etag) = &entry.meta.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = &entry.meta.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, last_modified);
+        }
+    }
+    let resp = match request.send().await {
+        Ok(resp) => resp,
+        Err(e) => return Err(format!("Error sending request: {}", e)),
+    };
+    let status = resp.status();
+    if status == StatusCode::NOT_MODIFIED {
+        if let Some(mut entry) = cached {
+            debug!("Not modified, using cached copy");
+            cache::touch(&mut entry.meta).await;
+            let hints = entry.meta.hints();
+            return Ok((Input::File(entry.body), hints));
+        }
+    }
+    if !status.is_success() {
+        return Err(format!("Error response: {}", status));
+    }
+    let header_value = |name: header::HeaderName| {
+        resp.headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_owned())
+    };
+    let meta = CacheMeta {
+        url: url.to_owned(),
+        etag: header_value(header::ETAG),
+        last_modified: header_value(header::LAST_MODIFIED),
+        content_type: header_value(header::CONTENT_TYPE),
+        content_encoding: header_value(header::CONTENT_ENCODING),
+        fetched_at: Utc::now(),
+    };
+    if resp.content_length().is_some_and(|length| length > limit()) {
+        return Err(decompress::exceeded(limit()));
+    }
+    debug!(status = status.as_u16(), "Downloading");
+    // if content.contains("hcaptcha.com") {
+    //     println!("{url}");
+    // }
+    let hints = meta.hints();
+    let pending = cache::begin(meta).await;
+    Ok((Input::Response(Box::new((resp, pending))), hints))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(line: &str) -> Option<Location> {
+        Source::parse(line, Path::new("/etc/lists")).map(|source| source.location)
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_list_file() {
+        assert_eq!(
+            location("extra/hosts.txt weight=2"),
+            Some(Location::Local(PathBuf::from("/etc/lists/extra/hosts.txt")))
+        );
+        assert_eq!(
+            location("https://example.com/hosts"),
+            Some(Location::Remote("https://example.com/hosts".to_owned()))
+        );
+        assert_eq!(location("# comment"), None);
+    }
+
+    #[test]
+    fn file_urls_are_decoded_absolute_paths() {
+        assert_eq!(
+            location("file:///srv/my%20lists.txt"),
+            Some(Location::Local(PathBuf::from("/srv/my lists.txt")))
+        );
+        assert_eq!(
+            location("file://localhost/srv/hosts.txt"),
+            Some(Location::Local(PathBuf::from("/srv/hosts.txt")))
+        );
+    }
+
+    #[test]
+    fn file_urls_on_other_hosts_are_rejected() {
+        assert_eq!(location("file://fileserver/srv/hosts.txt"), None);
+    }
+}
@@ -0,0 +1,163 @@
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use tracing::{info, warn};
+
+use crate::cli;
+
+// Copy of https://publicsuffix.org/list/public_suffix_list.dat built into the binary,
+// --public-suffix-list reads a newer one
+static BUNDLED: &str = include_str!("../public_suffix_list.dat");
+
+static PRIVATE_SECTION: &str = "// ===BEGIN PRIVATE DOMAINS===";
+
+pub static LIST: Lazy<SuffixList> = Lazy::new(|| {
+    let private = !cli::CLI.icann_suffixes_only;
+    let Some(path) = &cli::CLI.public_suffix_list else {
+        return SuffixList::parse(BUNDLED, private);
+    };
+    match std::fs::read_to_string(path) {
+        Ok(text) => {
+            let list = SuffixList::parse(&text, private);
+            info!(path = %path.display(), rules = list.len(), "Loaded Public Suffix List");
+            list
+        }
+        Err(e) => {
+            warn!(path = %path.display(), error = %e, "Error reading the Public Suffix List, using the bundled copy");
+            SuffixList::parse(BUNDLED, private)
+        }
+    }
+});
+
+// Rules of the list in ASCII form, wildcard rules by the name under the `*`
+#[derive(Default)]
+pub struct SuffixList {
+    rules: HashSet<String>,
+    wildcards: HashSet<String>,
+    exceptions: HashSet<String>,
+}
+
+impl SuffixList {
+    // The private section lists suffixes companies hand out, e.g. github.io or blogspot.com
+    fn parse(text: &str, private: bool) -> Self {
+        let mut list = SuffixList::default();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.starts_with(PRIVATE_SECTION) && !private {
+                break;
+            }
+            // Only the first word of a line is the rule
+            let Some(rule) = line.split_whitespace().next() else {
+                continue;
+            };
+            if rule.starts_with("//") {
+                continue;
+            }
+            let (set, rule) = if let Some(rule) = rule.strip_prefix('!') {
+                (&mut list.exceptions, rule)
+            } else if let Some(rule) = rule.strip_prefix("*.") {
+                (&mut list.wildcards, rule)
+            } else {
+                (&mut list.rules, rule)
+            };
+            if let Ok(rule) = idna::domain_to_ascii(rule) {
+                set.insert(rule);
+            }
+        }
+        list
+    }
+
+    fn len(&self) -> usize {
+        self.rules.len() + self.wildcards.len() + self.exceptions.len()
+    }
+
+    // Where the public suffix of the name starts, the top level label when no rule matches
+    fn suffix_start(&self, name: &str) -> usize {
+        let mut start = 0;
+        loop {
+            let candidate = &name[start..];
+            let next = candidate.find('.').map(|dot| start + dot + 1);
+            // An exception makes the name under the wildcard registrable, `!city.kobe.jp`
+            if self.exceptions.contains(candidate) {
+                return next.unwrap_or(start);
+            }
+            if self.rules.contains(candidate)
+                || next.is_some_and(|next| self.wildcards.contains(&name[next..]))
+            {
+                return start;
+            }
+            match next {
+                Some(next) => start = next,
+                None => return start,
+            }
+        }
+    }
+
+    pub fn is_public_suffix(&self, name: &str) -> bool {
+        self.suffix_start(name) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static RULES: &str = "\
+// ===BEGIN ICANN DOMAINS===
+com
+uk
+co.uk
+jp
+kobe.jp
+*.kobe.jp
+!city.kobe.jp
+// 公司.cn
+公司.cn
+// ===END ICANN DOMAINS===
+// ===BEGIN PRIVATE DOMAINS===
+github.io // with a comment after the rule
+// ===END PRIVATE DOMAINS===
+";
+
+    #[test]
+    fn plain_rules() {
+        let list = SuffixList::parse(RULES, true);
+        assert!(list.is_public_suffix("com"));
+        assert!(list.is_public_suffix("co.uk"));
+        assert!(!list.is_public_suffix("example.co.uk"));
+        assert!(!list.is_public_suffix("example.com"));
+        // Punycode of 公司.cn
+        assert!(list.is_public_suffix("xn--55qx5d.cn"));
+    }
+
+    #[test]
+    fn wildcards_and_exceptions() {
+        let list = SuffixList::parse(RULES, true);
+        assert!(list.is_public_suffix("kobe.jp"));
+        assert!(list.is_public_suffix("any.kobe.jp"));
+        assert!(!list.is_public_suffix("www.any.kobe.jp"));
+        // The exception makes the name under the wildcard registrable
+        assert!(!list.is_public_suffix("city.kobe.jp"));
+        assert!(!list.is_public_suffix("www.city.kobe.jp"));
+    }
+
+    #[test]
+    fn unlisted_top_level_labels_are_suffixes() {
+        let list = SuffixList::parse(RULES, true);
+        assert!(list.is_public_suffix("example"));
+        assert!(!list.is_public_suffix("a.example"));
+    }
+
+    #[test]
+    fn the_private_section_is_optional() {
+        assert!(SuffixList::parse(RULES, true).is_public_suffix("github.io"));
+        assert!(!SuffixList::parse(RULES, false).is_public_suffix("github.io"));
+    }
+
+    #[test]
+    fn the_bundled_list_parses() {
+        let list = SuffixList::parse(BUNDLED, true);
+        assert!(list.is_public_suffix("co.uk"));
+        assert!(list.is_public_suffix("blogspot.com"));
+        assert!(!list.is_public_suffix("bbc.co.uk"));
+    }
+}
@@ -1,191 +1,587 @@
 use futures::future::join_all;
-use once_cell::sync::Lazy;
-use std::time::Duration;
-
-use regex::Regex;
-use reqwest::Client;
-use std;
-use std::borrow::Cow;
 use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
 use tokio::fs::read_to_string;
+use tracing::{info, info_span, warn, Instrument};
+
+use crate::cli;
+use crate::cloudflare;
+use crate::metrics;
+use crate::parser;
+use crate::quarantine;
+use crate::report::{Outcome, SourceReport, SourceStatus};
+use crate::source::Source;
+use crate::suffix;
+
+pub struct ListContent {
+    pub domains: HashSet<String>,
+    pub sources: Vec<SourceReport>,
+    pub whitelisted: usize,
+    pub collapsed: usize,
+    // Blocked names left out for a blocked ancestor, exact-match exports still list them
+    pub subdomains: HashSet<String>,
+    pub public_suffixes: usize,
+    // Names of the exception rules and $denyallow carve-outs, they belong to the allow list of the run
+    pub allow: HashSet<String>,
+    pub exceptions: usize,
+    pub carve_outs: usize,
+    // Blocked names dropped because an exception covers them
+    pub excepted: usize,
+    // Exceptions and carve-outs below a name that stays blocked, Gateway blocks them all the same
+    pub shadowed_exceptions: usize,
+    pub shadowed_carve_outs: usize,
+    // Names hosts sources point at a real address, the first source listing a name wins
+    pub overrides: HashMap<String, IpAddr>,
+    // Set when failed sources exceed the budget, the domains are then incomplete
+    pub failure: Option<String>,
+    // New accepted copies of the sources, committed once the run went through
+    pub staged: Vec<PathBuf>,
+}
+
+struct SourceContent {
+    report: SourceReport,
+    domains: HashSet<String>,
+    parsed: parser::Parsed,
+}
+
+// Names covered by exception rules, on their own or with their subdomains
+#[derive(Default)]
+struct Exceptions {
+    exact: HashSet<String>,
+    wide: HashSet<String>,
+}
+
+impl Exceptions {
+    fn insert(&mut self, exception: &parser::Exception) {
+        self.exact.insert(exception.name.to_owned());
+        if exception.subdomains {
+            self.wide.insert(exception.name.to_owned());
+        }
+    }
+
+    fn covers(&self, domain: &str) -> bool {
+        self.exact.contains(domain)
+            || domain
+                .match_indices('.')
+                .any(|(i, _)| self.wide.contains(&domain[i + 1..]))
+    }
+}
 
 pub async fn read_file_content_and_download(
-    name: &str,
+    name: &Path,
     skip_filter: bool,
-    white_list: Option<HashSet<String>>,
-) -> HashSet<String> {
-    let urls = read_file_content(&name).await;
-    let content = get_content_from_urls(&urls, &skip_filter, &white_list).await;
-    return content;
+    white_list: Option<&HashSet<String>>,
+) -> ListContent {
+    let sources = read_file_content(name).await;
+    let list_name = name.file_name().unwrap_or_default().to_string_lossy();
+    let content = get_content_from_urls(&list_name, &sources, &skip_filter, white_list).await;
+    content
 }
 
-pub async fn read_file_content(name: &str) -> Vec<String> {
+pub async fn read_file_content(name: &Path) -> Vec<Source> {
+    let base_dir = name.parent().unwrap_or(Path::new(""));
     let content = match read_to_string(name).await {
         Ok(content) => content
             .lines()
-            .filter_map(|line| {
-                if line.starts_with('#') {
-                    return None;
-                }
-                return Some(line.to_string());
-            })
+            .filter_map(|line| Source::parse(line, base_dir))
             .collect::<Vec<_>>(),
-        Err(e) => panic!("Error reading file: {}", e),
+        Err(e) => panic!("Error reading file {}: {}", name.display(), e),
     };
-    return content;
+    content
 }
 
-static CLIENT: Lazy<Client> = Lazy::new(|| {
-    let client = Client::builder()
-        .pool_idle_timeout(Some(Duration::from_secs(600)))
-        .tcp_keepalive(Some(Duration::from_secs(60)))
-        .build()
-        .unwrap();
-    return client;
-});
+// Collects every item of the given Cloudflare lists, None when any of them can't be read. A list
+// whose items don't add up to its count is unreadable too, the removals would be undercounted
+pub async fn get_deployed_domains(lists: &[serde_json::Value]) -> Option<HashSet<String>> {
+    let mut deployed = HashSet::new();
+    for list in lists {
+        let id = list["id"].as_str()?;
+        let items = cloudflare::get_cf_list_items(id).await?;
+        let count = list["count"].as_u64();
+        if count != Some(items.len() as u64) {
+            warn!(
+                list_id = id,
+                count,
+                items = items.len(),
+                "List items don't match the list count"
+            );
+            return None;
+        }
+        deployed.extend(items);
+    }
+    Some(deployed)
+}
 
 async fn get_content_from_urls(
-    urls: &Vec<String>,
+    name: &str,
+    sources: &[Source],
     skip_filter: &bool,
-    white_list: &Option<HashSet<String>>,
-) -> HashSet<String> {
-    let tasks = urls
+    white_list: Option<&HashSet<String>>,
+) -> ListContent {
+    let tasks = sources
         .iter()
-        .map(|url| download_content(&url))
+        .map(|source| {
+            source
+                .fetch()
+                .instrument(info_span!("download", source_url = %source))
+        })
         .collect::<Vec<_>>();
-    let content = join_all(tasks)
-        .await
+    let mut per_source = Vec::new();
+    let mut staged = Vec::new();
+    for (source, fetched) in sources.iter().zip(join_all(tasks).await) {
+        metrics::SOURCE_FAILED
+            .with_label_values(&[name, &source.name])
+            .set((fetched.status != SourceStatus::Ok) as i64);
+        let (body, encoding, decode_issue) = match fetched.body {
+            Some(decoded) => (decoded.text, decoded.encoding, decoded.issue),
+            None => (String::new(), "", None),
+        };
+        let format = source
+            .options
+            .format
+            .unwrap_or_else(|| parser::detect(&body));
+        let mut parsed = parser::parse(&body, format, &source.options.selector);
+        let mut domains = HashSet::new();
+        for domain in parsed.block.drain(..) {
+            if domains.contains(&domain) {
+                parsed.outcomes.record(Outcome::Duplicate, &domain);
+            } else {
+                domains.insert(domain);
+            }
+        }
+        // An allow list written as `@@||example.com^` rules means those names, not exceptions to itself,
+        // and the names it redirects are meant to resolve
+        if *skip_filter {
+            domains.extend(parsed.allow.drain(..).map(|exception| exception.name));
+            domains.extend(parsed.overrides.drain(..).map(|(name, _)| name));
+        }
+        info!(list = name, source_url = %source, format = format.as_str(), domains = domains.len(), "Parsed source");
+        metrics::SOURCE_DOMAINS
+            .with_label_values(&[name, &source.name])
+            .set(domains.len() as i64);
+        let mut report = SourceReport {
+            list: name.to_owned(),
+            url: source.name.to_owned(),
+            status: fetched.status,
+            error: fetched.error,
+            weight: source.options.weight,
+            format: format.as_str().to_owned(),
+            encoding: encoding.to_owned(),
+            decode_issue,
+            bytes: body.len(),
+            lines: body.lines().count(),
+            // Taken before a quarantine, they tell what was wrong with the new copy
+            outcomes: std::mem::take(&mut parsed.outcomes),
+            ..Default::default()
+        };
+        let previous = quarantine::load(name, &source.name).await;
+        report.previous_domains = previous.as_ref().map(|previous| previous.len());
+        if report.status != SourceStatus::Failed {
+            match quarantine::inspect(&body, &domains, previous.as_ref()) {
+                Some(reason) if !cli::CLI.accept_anomalies => {
+                    match previous.filter(|previous| !previous.is_empty()) {
+                        Some(previous) => {
+                            warn!(list = name, source_url = %source, reason, "Quarantined source, keeping its last accepted copy");
+                            report.status = SourceStatus::Quarantined;
+                            report.error = Some(reason);
+                            domains = previous;
+                        }
+                        None => {
+                            warn!(list = name, source_url = %source, reason, "Quarantined source without an accepted copy");
+                            report.status = SourceStatus::Failed;
+                            report.error = Some(format!("{reason}, no accepted copy to keep"));
+                            domains.clear();
+                        }
+                    }
+                    // Only the names of the accepted copy are kept, not its rules
+                    parsed = parser::Parsed::default();
+                }
+                _ => staged.extend(quarantine::stage(name, &source.name, &domains).await),
+            }
+        }
+        metrics::SOURCE_QUARANTINED
+            .with_label_values(&[name, &source.name])
+            .set((report.status == SourceStatus::Quarantined) as i64);
+        report.domains = domains.len();
+        report.exceptions = parsed.allow.len();
+        report.overrides = parsed.overrides.len();
+        report.skipped_rules = parsed.skipped;
+        per_source.push(SourceContent {
+            report,
+            domains,
+            parsed,
+        });
+    }
+    let reports = per_source
         .iter()
-        .map(|x| x.lines())
-        .flatten()
-        .filter_map(|x| filter_domain(x, &white_list))
-        .collect::<HashSet<_>>();
+        .map(|source| &source.report)
+        .collect::<Vec<_>>();
+    let failure = check_failure_budget(name, &reports);
 
-    if *skip_filter {
-        return content;
+    // Count how many sources list each domain to find what every source adds on its own
+    let mut seen_in: HashMap<&String, usize> = HashMap::new();
+    for source in &per_source {
+        for domain in &source.domains {
+            *seen_in.entry(domain).or_default() += 1;
+        }
+    }
+    let mut content = HashSet::new();
+    let mut public_suffixes = HashSet::new();
+    let mut sources = Vec::new();
+    for source in &per_source {
+        let mut report = source.report.clone();
+        for domain in &source.domains {
+            // Allowing a suffix is harmless, blocking one blocks every site under it
+            if !*skip_filter && suffix::LIST.is_public_suffix(domain) {
+                report.outcomes.record(Outcome::PublicSuffix, domain);
+                public_suffixes.insert(domain);
+                continue;
+            }
+            if white_list.is_some_and(|w| w.contains(domain)) {
+                report.whitelisted += 1;
+                report.outcomes.record(Outcome::Whitelisted, domain);
+                continue;
+            }
+            report.outcomes.record(Outcome::Accepted, domain);
+            if seen_in.get(domain) == Some(&1) {
+                report.unique += 1;
+            }
+            content.insert(domain.to_owned());
+        }
+        sources.push(report);
     }
 
-    return filter_subdomain(&content);
-}
+    let whitelisted = seen_in.len() - content.len() - public_suffixes.len();
+    if !public_suffixes.is_empty() {
+        warn!(list = name, count = public_suffixes.len(), names = ?public_suffixes, "Refused public suffixes as block entries");
+    }
+    metrics::DOMAINS_DROPPED
+        .with_label_values(&["public_suffix"])
+        .set(public_suffixes.len() as i64);
+    if white_list.is_some() {
+        info!(dropped = whitelisted, "Removed whitelisted domains");
+        metrics::DOMAINS_DROPPED
+            .with_label_values(&["whitelist"])
+            .set(whitelisted as i64);
+    }
 
-fn filter_subdomain(filtered_content: &HashSet<String>) -> HashSet<String> {
-    let mut domain_map: HashMap<Cow<String>, HashSet<Cow<String>>> = HashMap::new();
+    if *skip_filter {
+        return ListContent {
+            domains: content,
+            sources,
+            whitelisted,
+            collapsed: 0,
+            subdomains: HashSet::new(),
+            public_suffixes: 0,
+            allow: HashSet::new(),
+            exceptions: 0,
+            carve_outs: 0,
+            excepted: 0,
+            shadowed_exceptions: 0,
+            shadowed_carve_outs: 0,
+            overrides: HashMap::new(),
+            failure,
+            staged,
+        };
+    }
 
-    for domain in filtered_content {
-        let splitted = domain.split('.').collect::<Vec<_>>();
-        if splitted.len() <= 1 {
-            continue;
+    // Exceptions win over blocks from every source, like lists loaded together in AdGuard Home,
+    // except over $important blocks, which only $important exceptions lift
+    let mut exceptions = Exceptions::default();
+    let mut important_exceptions = Exceptions::default();
+    let mut important = HashSet::new();
+    let mut carve_out_names = HashSet::new();
+    for source in &per_source {
+        for exception in &source.parsed.allow {
+            exceptions.insert(exception);
+            if exception.important {
+                important_exceptions.insert(exception);
+            }
+        }
+        important.extend(source.parsed.important.iter());
+        carve_out_names.extend(source.parsed.carve_outs.iter().cloned());
+    }
+    let carve_outs = carve_out_names.len();
+    let before = content.len();
+    content.retain(|domain| {
+        if important_exceptions.covers(domain) {
+            return false;
         }
-        let domain_part = splitted[splitted.len() - 2..].join(".");
-        domain_map
-            .entry(Cow::Owned(domain_part))
-            .or_insert(HashSet::new())
-            .insert(Cow::Borrowed(domain));
+        important.contains(domain) || !exceptions.covers(domain)
+    });
+    let excepted = before - content.len();
+    if !exceptions.exact.is_empty() {
+        info!(
+            exceptions = exceptions.exact.len(),
+            dropped = excepted,
+            "Applied exception rules"
+        );
     }
+    metrics::DOMAINS_DROPPED
+        .with_label_values(&["exception"])
+        .set(excepted as i64);
+    let exception_count = exceptions.exact.len();
+    // An exception beaten by an $important block is not part of the allow list either
+    let exception_names = exceptions
+        .exact
+        .into_iter()
+        .filter(|name| !content.contains(name))
+        .collect::<Vec<_>>();
+    let mut allow = carve_out_names.clone();
+    allow.extend(exception_names.iter().cloned());
 
-    let filtered_domains = domain_map
-        .iter()
-        .filter_map(|(domain_part, domain_names)| {
-            if domain_names.contains(domain_part) {
-                Some(HashSet::from([domain_part.to_string()]))
-            } else {
-                Some(
-                    domain_names
-                        .iter()
-                        .map(|l| l.to_string())
-                        .collect::<HashSet<_>>(),
-                )
+    // A name another source blocks is not redirected
+    let mut overrides = HashMap::new();
+    for source in &per_source {
+        for (name, ip) in &source.parsed.overrides {
+            if !content.contains(name) {
+                overrides.entry(name.to_owned()).or_insert(*ip);
             }
-        })
-        .flatten()
-        .collect::<HashSet<_>>();
-    return filtered_domains;
-}
+        }
+    }
 
-async fn download_content(url: &str) -> String {
-    let resp = match CLIENT.get(url).send().await {
-        Ok(resp) => resp,
-        Err(e) => panic!("Error sending request: {}", e),
-    };
-    let content = match resp.text().await {
-        Ok(content) => content,
-        Err(e) => panic!("Error reading response: {}", e),
-    };
-    // println!("Downloaded: {} . File size: {}", url, content.len());
-    // if content.contains("hcaptcha.com") {
-    //     println!("{url}");
-    // }
-    return content;
+    let mut subdomains = content;
+    let content = filter_subdomain(&subdomains, white_list.into_iter().flatten().chain(&allow));
+    subdomains.retain(|domain| !content.contains(domain));
+    let collapsed = subdomains.len();
+    info!(saved = collapsed, "Collapsed subdomains under listed names");
+    metrics::DOMAINS_DROPPED
+        .with_label_values(&["subdomain"])
+        .set(collapsed as i64);
+    let shadowed = shadowed_by_block(&exception_names, &content);
+    if !shadowed.is_empty() {
+        warn!(list = name, count = shadowed.len(), names = ?shadowed, "Exceptions below a blocked name, Gateway still blocks them");
+    }
+    let shadowed_carve_outs = shadowed_by_block(&carve_out_names, &content);
+    if !shadowed_carve_outs.is_empty() {
+        warn!(list = name, count = shadowed_carve_outs.len(), names = ?shadowed_carve_outs, "$denyallow carve-outs below a blocked name, Gateway still blocks them");
+    }
+    ListContent {
+        domains: content,
+        sources,
+        whitelisted,
+        collapsed,
+        subdomains,
+        public_suffixes: public_suffixes.len(),
+        allow,
+        exceptions: exception_count,
+        carve_outs,
+        excepted,
+        shadowed_exceptions: shadowed.len(),
+        shadowed_carve_outs: shadowed_carve_outs.len(),
+        overrides,
+        failure,
+        staged,
+    }
 }
 
-static REPLACE_PATTERN: Lazy<Regex> =
-    Lazy::new(
-        || match Regex::new(r"(^([0-9.]+|[0-9a-fA-F:.]+)\s+|^(\|\||@@\|\|?|\*\.|\*))") {
-            Ok(re) => re,
-            Err(e) => panic!("Error compiling regex: {}", e),
-        },
+// Only sources that failed without a cached copy to fall back to count against the budget
+fn check_failure_budget(name: &str, reports: &[&SourceReport]) -> Option<String> {
+    let failed = reports
+        .iter()
+        .filter(|report| report.status == SourceStatus::Failed)
+        .collect::<Vec<_>>();
+    if failed.is_empty() {
+        return None;
+    }
+    let total_weight = reports.iter().map(|report| report.weight).sum::<f64>();
+    let failed_weight = failed.iter().map(|report| report.weight).sum::<f64>();
+    let failed_percent = if total_weight > 0.0 {
+        failed_weight / total_weight * 100.0
+    } else {
+        0.0
+    };
+    warn!(
+        list = name,
+        failed = failed.len(),
+        failed_weight_percent = failed_percent,
+        "Some sources failed"
     );
+    if let Some(max) = cli::CLI
+        .max_failed_sources
+        .filter(|max| failed.len() > *max)
+    {
+        return Some(format!(
+            "{} sources of {} failed, more than the allowed {}",
+            failed.len(),
+            name,
+            max
+        ));
+    }
+    if failed_percent > cli::CLI.max_failed_weight {
+        return Some(format!(
+            "Failed sources of {} carry {:.1}% of the weight, more than the allowed {}%",
+            name,
+            failed_percent,
+            cli::CLI.max_failed_weight
+        ));
+    }
+    None
+}
 
-static DOMAIN_PATTERN: Lazy<Regex> = Lazy::new(|| {
-    match Regex::new(
-        r"^([a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9\-]*[a-zA-Z0-9])(\.([a-zA-Z0-9]|[a-zA-Z0-9][a-zA-Z0-9\-]*[a-zA-Z0-9]))*$",
-    ) {
-        Ok(re) => re,
-        Err(e) => panic!("Error compiling regex: {}", e),
-    }
-});
-
-static IP_PATTERN: Lazy<Regex> =
-    Lazy::new(
-        || match Regex::new(r"^\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}$") {
-            Ok(re) => re,
-            Err(e) => panic!("Error compiling regex: {}", e),
-        },
-    );
+// A node per label, the top level labels at the root
+#[derive(Default)]
+struct LabelTrie<'a> {
+    children: HashMap<&'a str, LabelTrie<'a>>,
+    blocked: Option<&'a String>,
+    allowed: bool,
+}
 
-fn filter_domain(line: &str, white_list: &Option<HashSet<String>>) -> Option<String> {
-    let domain = line.trim();
-    if domain.starts_with('#')
-        || domain.starts_with('!')
-        || domain.starts_with('/')
-        || domain.is_empty()
-    {
-        return None;
+impl<'a> LabelTrie<'a> {
+    fn insert(&mut self, name: &'a String) {
+        let node = name
+            .rsplit('.')
+            .fold(self, |node, label| node.children.entry(label).or_default());
+        node.blocked = Some(name);
     }
 
-    let domain = domain
-        .to_lowercase()
-        .split('#')
-        .next()
-        .and_then(|x| x.split('^').next())
-        .and_then(|x| x.split('$').next())
-        .and_then(|x| Some(x.replace('\r', "")))
-        .and_then(|x| Some(x.trim().to_string()))
-        .and_then(|x| Some(x.trim_start_matches("*.").to_string()))
-        .and_then(|x| Some(x.trim_start_matches(".").to_string()))
-        .and_then(|x| Some(REPLACE_PATTERN.replace_all(&x, "").to_string()))
-        .and_then(|x| match idna::domain_to_ascii(&x) {
-            Ok(domain) => Some(domain),
-            Err(_) => None,
-        })
-        .and_then(|x| {
-            if !DOMAIN_PATTERN.is_match(&x) || IP_PATTERN.is_match(&x) {
-                None
-            } else {
-                Some(x)
+    // Only allowed names under a blocked one matter, the others aren't added
+    fn allow(&mut self, name: &str) {
+        let mut node = self;
+        for label in name.rsplit('.') {
+            match node.children.get_mut(label) {
+                Some(child) => node = child,
+                None => return,
             }
+        }
+        node.allowed = true;
+    }
+
+    // Blocked names without a blocked ancestor, an allowed name in between keeps its subdomains
+    fn collect(&self, covered: bool, kept: &mut HashSet<String>) {
+        let covered = covered && !self.allowed;
+        if let Some(name) = self.blocked.filter(|_| !covered) {
+            kept.insert(name.to_owned());
+        }
+        for child in self.children.values() {
+            child.collect(covered || self.blocked.is_some(), kept);
+        }
+    }
+}
+
+// Gateway blocks the subdomains of a listed name, so a listed ancestor at any depth makes a name
+// redundant. An allowed name stops that, its blocked subdomains have to stay listed
+fn filter_subdomain<'a>(
+    filtered_content: &HashSet<String>,
+    allowed: impl Iterator<Item = &'a String>,
+) -> HashSet<String> {
+    let mut trie = LabelTrie::default();
+    for domain in filtered_content {
+        trie.insert(domain);
+    }
+    for name in allowed {
+        trie.allow(name);
+    }
+    let mut kept = HashSet::new();
+    trie.collect(false, &mut kept);
+    kept
+}
+
+// Allowed names with a blocked ancestor. Gateway blocks the subdomains of a listed name and the
+// lists carry no allow rules, so these names stay blocked whatever the sources say
+fn shadowed_by_block<'a>(
+    names: impl IntoIterator<Item = &'a String>,
+    blocked: &HashSet<String>,
+) -> Vec<&'a String> {
+    names
+        .into_iter()
+        .filter(|name| {
+            name.match_indices('.')
+                .any(|(i, _)| blocked.contains(&name[i + 1..]))
         })
-        .and_then(|x| Some(x.trim_start_matches("www.").to_string()))
-        .and_then(|x| match white_list {
-            Some(white_list) => {
-                if white_list.contains(&x) {
-                    None
-                } else {
-                    Some(x)
-                }
-            }
-            None => Some(x),
-        });
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    return domain;
+    fn set(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn exceptions_cover_subdomains_only_when_asked() {
+        let mut exceptions = Exceptions::default();
+        for (name, subdomains) in [("wide.example.com", true), ("exact.example.com", false)] {
+            exceptions.insert(&parser::Exception {
+                name: name.to_owned(),
+                subdomains,
+                important: false,
+            });
+        }
+        assert!(exceptions.covers("wide.example.com"));
+        assert!(exceptions.covers("a.b.wide.example.com"));
+        assert!(exceptions.covers("exact.example.com"));
+        assert!(!exceptions.covers("a.exact.example.com"));
+        assert!(!exceptions.covers("example.com"));
+    }
+
+    fn collapse(blocked: &[&str], allowed: &[&str]) -> Vec<String> {
+        let allowed = allowed
+            .iter()
+            .map(|name| name.to_string())
+            .collect::<Vec<_>>();
+        let mut kept = filter_subdomain(&set(blocked), allowed.iter())
+            .into_iter()
+            .collect::<Vec<_>>();
+        kept.sort_unstable();
+        kept
+    }
+
+    #[test]
+    fn subdomains_collapse_under_a_blocked_ancestor_at_any_depth() {
+        assert_eq!(
+            collapse(
+                &[
+                    "tracker.example.com",
+                    "a.b.tracker.example.com",
+                    "x.tracker.example.com",
+                    "example.org",
+                    "ample.com"
+                ],
+                &[]
+            ),
+            ["ample.com", "example.org", "tracker.example.com"]
+        );
+    }
+
+    #[test]
+    fn an_allowed_name_in_between_keeps_its_blocked_subdomains() {
+        assert_eq!(
+            collapse(
+                &[
+                    "tracker.example.com",
+                    "a.ok.tracker.example.com",
+                    "b.c.ok.tracker.example.com",
+                    "d.tracker.example.com"
+                ],
+                &["ok.tracker.example.com", "unrelated.example.net"]
+            ),
+            [
+                "a.ok.tracker.example.com",
+                "b.c.ok.tracker.example.com",
+                "tracker.example.com"
+            ]
+        );
+    }
+
+    #[test]
+    fn an_allowed_name_without_a_blocked_ancestor_changes_nothing() {
+        assert_eq!(
+            collapse(&["a.example.com", "b.a.example.com"], &["example.com"]),
+            ["a.example.com"]
+        );
+    }
+
+    #[test]
+    fn allowed_names_under_a_blocked_ancestor_are_shadowed() {
+        let blocked = set(&["example.com", "b.test.net"]);
+        let allowed = ["a.example.com", "test.net", "c.b.test.net", "other.org"].map(String::from);
+        assert_eq!(
+            shadowed_by_block(&allowed, &blocked),
+            ["a.example.com", "c.b.test.net"]
+        );
+    }
 }